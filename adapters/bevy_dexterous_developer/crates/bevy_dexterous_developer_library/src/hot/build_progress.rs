@@ -0,0 +1,33 @@
+use std::sync::Mutex;
+
+use bevy::prelude::*;
+use dexterous_developer_instance::internal::HOT_RELOAD_INFO;
+use dexterous_developer_types::BuildProgress;
+
+use crate::HotReloadBuildProgress;
+
+static LATEST_BUILD_PROGRESS: Mutex<Option<BuildProgress>> = Mutex::new(None);
+
+pub fn register_build_progress_callback() {
+    let Some(info) = HOT_RELOAD_INFO.get() else {
+        return;
+    };
+
+    info.clone().build_progress_callback(|progress| {
+        if let Ok(mut latest) = LATEST_BUILD_PROGRESS.lock() {
+            *latest = Some(progress);
+        }
+    });
+}
+
+pub fn sync_build_progress(mut build_progress: ResMut<HotReloadBuildProgress>) {
+    let Some(progress) = LATEST_BUILD_PROGRESS
+        .lock()
+        .ok()
+        .and_then(|mut latest| latest.take())
+    else {
+        return;
+    };
+
+    build_progress.0 = (!progress.finished).then_some(progress);
+}
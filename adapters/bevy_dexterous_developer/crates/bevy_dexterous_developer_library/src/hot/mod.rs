@@ -1,3 +1,4 @@
+mod build_progress;
 mod hot_reload_internal;
 mod reload_systems;
 mod reloadable_app;
@@ -12,6 +13,7 @@ use bevy::prelude::{App, First, Plugin, PreStartup, Update};
 
 pub extern crate libloading;
 
+use crate::hot::build_progress::{register_build_progress_callback, sync_build_progress};
 use crate::hot::hot_reload_internal::draw_internal_hot_reload;
 use crate::hot::reload_systems::{
     reset_update_frame, toggle_reload_mode, toggle_reloadable_elements, InternalHotReload,
@@ -52,11 +54,14 @@ impl Plugin for HotReloadPlugin {
             .init_resource::<ReloadableAppCleanupData>()
             .init_resource::<ReplacableResourceStore>()
             .init_resource::<ReplacableComponentStore>()
+            .init_resource::<HotReloadBuildProgress>()
             .insert_resource(InternalHotReload(chrono::Local::now(), false));
 
+        register_build_progress_callback();
+
         app.add_systems(PreStartup, reload)
             .add_systems(CleanupSchedules, cleanup_schedules)
-            .add_systems(First, reload)
+            .add_systems(First, (reload, sync_build_progress))
             .add_systems(Last, reset_update_frame)
             .add_systems(
                 Update,
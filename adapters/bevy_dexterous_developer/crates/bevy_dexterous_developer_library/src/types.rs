@@ -6,6 +6,7 @@ use bevy::{
     prelude::*,
     state::{app::StatesPlugin, state::FreelyMutableState},
 };
+use dexterous_developer_types::BuildProgress;
 use serde::{de::DeserializeOwned, Serialize};
 
 pub type Result<T> = anyhow::Result<T>;
@@ -197,6 +198,11 @@ impl Default for ReloadSettings {
     }
 }
 
+/// The progress of the build currently running on the hot reload server, or `None` while no build is running.
+/// Only inserted when hot reloading, so access it with `Option<Res<HotReloadBuildProgress>>`.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct HotReloadBuildProgress(pub Option<BuildProgress>);
+
 /// These are the different modes for hot-reloading
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum ReloadableElementPolicy {
@@ -0,0 +1,85 @@
+use std::{collections::HashSet, time::Duration};
+
+use camino::Utf8PathBuf;
+use debounced::debounced;
+use dexterous_developer_types::Target;
+use tokio::{select, sync::broadcast};
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
+use tracing::{info, trace};
+
+use crate::types::{BuildOutputMessages, BuilderIncomingMessages};
+
+/// How long to wait for changes to settle before starting a build
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+
+/// The parts of a builder's event loop that differ between builders
+pub trait BuildEvents: Send + 'static {
+    /// Starts a build, or queues one if a build is already running
+    fn build(&mut self);
+
+    /// Whether a change to these files should start a build
+    fn is_relevant(&mut self, paths: &[Utf8PathBuf]) -> bool;
+
+    /// Runners reported which of this target's builds they have loaded
+    fn loaded_builds(&mut self, _build_ids: HashSet<u32>) {}
+}
+
+/// Reacts to the messages a builder receives until the incoming channel closes.
+///
+/// Build requests and relevant code changes are debounced, and nothing is built until a runner
+/// first requests a build of this target. Asset changes are passed straight on to the output.
+pub async fn run_event_loop(
+    target: Target,
+    mut incoming: broadcast::Receiver<BuilderIncomingMessages>,
+    output: broadcast::Sender<BuildOutputMessages>,
+    mut events: impl BuildEvents,
+) {
+    let (build_trigger, build_trigger_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let mut debounced = debounced(
+        UnboundedReceiverStream::new(build_trigger_rx),
+        DEBOUNCE_DELAY,
+    );
+    let mut first_build_triggered = false;
+
+    loop {
+        select! {
+            Some(()) = debounced.next() => {
+                if first_build_triggered {
+                    events.build();
+                } else {
+                    info!("Not building {target} yet");
+                }
+            }
+            Ok(recv) = incoming.recv() => {
+                match recv {
+                    BuilderIncomingMessages::RequestBuild(request) => {
+                        if target == request {
+                            info!("Build Request");
+                            first_build_triggered = true;
+                            let _ = build_trigger.send(());
+                        }
+                    }
+                    BuilderIncomingMessages::CodeChanged(paths) => {
+                        if events.is_relevant(&paths) {
+                            info!("Code Changed");
+                            let _ = build_trigger.send(());
+                        } else {
+                            trace!("Ignoring code change for {target} - {paths:?}");
+                        }
+                    }
+                    BuilderIncomingMessages::AssetChanged(asset) => {
+                        trace!("Builder Received Asset Change - {asset:?}");
+                        let _ = output.send(BuildOutputMessages::AssetUpdated(asset));
+                    }
+                    BuilderIncomingMessages::LoadedBuilds { target: loaded_target, build_ids } => {
+                        if loaded_target == target {
+                            trace!("Runners have builds {build_ids:?} loaded");
+                            events.loaded_builds(build_ids);
+                        }
+                    }
+                }
+            }
+            else => { break }
+        };
+    }
+}
@@ -0,0 +1,496 @@
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::bail;
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::{BuilderTypes, Target, TargetBuildSettings};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::Mutex,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    build_loop::{run_event_loop, BuildEvents},
+    default_builder::{
+        dylib_dependencies::DependencyGraph,
+        library_hashes::{LibraryChanges, LibraryHashes},
+        toolchain::TOOLCHAIN_VARIABLE,
+    },
+    types::{
+        BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
+        BuilderOutgoingMessages, CompletedBuild,
+    },
+    watch_filter::CodeWatchFilter,
+};
+
+/// A line of JSON written to stdout by a build command. Any other output is passed through.
+///
+/// For example, `{"type": "root_library", "path": "target/debug/libgame.so"}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandBuildMessage {
+    /// A library the build produced, which runners also need
+    Library { path: Utf8PathBuf },
+    /// The library runners load - a build needs one to succeed
+    RootLibrary { path: Utf8PathBuf },
+    /// The build failed, even if the command exits successfully
+    Failed { message: String },
+}
+
+impl CommandBuildMessage {
+    /// Reads a message from a line of output, if it is one
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if !line.starts_with('{') {
+            return None;
+        }
+        serde_json::from_str(line).ok()
+    }
+}
+
+pub struct CommandBuilderInitializer {
+    target: Target,
+    settings: TargetBuildSettings,
+}
+
+impl CommandBuilderInitializer {
+    pub fn new(target: Target, settings: TargetBuildSettings) -> Self {
+        Self { target, settings }
+    }
+}
+
+impl BuilderInitializer for CommandBuilderInitializer {
+    type Inner = CommandBuilder;
+
+    fn initialize_builder(
+        self,
+        channel: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
+    ) -> anyhow::Result<Self::Inner> {
+        CommandBuilder::new(self.target, self.settings, channel)
+    }
+}
+
+/// A builder that runs an external command, such as a script or a Makefile step, instead of cargo
+pub struct CommandBuilder {
+    target: Target,
+    settings: TargetBuildSettings,
+    outgoing: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
+    output: tokio::sync::broadcast::Sender<BuildOutputMessages>,
+    #[allow(dead_code)]
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl CommandBuilder {
+    pub fn new(
+        target: Target,
+        settings: TargetBuildSettings,
+        incoming: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
+    ) -> anyhow::Result<Self> {
+        if settings.build_command.is_empty() {
+            bail!("The Command builder for {target} needs a build_command");
+        }
+
+        let incoming_rx = incoming.subscribe();
+        let (outgoing_tx, _) = tokio::sync::broadcast::channel(100);
+        let (output_tx, _) = tokio::sync::broadcast::channel(100);
+        let code_filter = CodeWatchFilter::new(&settings.code_watch_folders, &settings.ignore)?;
+
+        let handle = tokio::spawn(run_event_loop(
+            target.clone(),
+            incoming_rx,
+            output_tx.clone(),
+            CommandBuildEvents {
+                target: target.clone(),
+                settings: settings.clone(),
+                id: Arc::new(AtomicU32::new(1)),
+                build_active: Arc::new(AtomicBool::new(false)),
+                build_pending: Arc::new(AtomicBool::new(false)),
+                library_hashes: Arc::new(Mutex::new(LibraryHashes::default())),
+                outgoing_tx: outgoing_tx.clone(),
+                output_tx: output_tx.clone(),
+                code_filter,
+            },
+        ));
+
+        Ok(Self {
+            target,
+            settings,
+            outgoing: outgoing_tx,
+            output: output_tx,
+            handle,
+        })
+    }
+}
+
+/// The state the command builder's event loop shares with its builds
+struct CommandBuildEvents {
+    target: Target,
+    settings: TargetBuildSettings,
+    id: Arc<AtomicU32>,
+    build_active: Arc<AtomicBool>,
+    build_pending: Arc<AtomicBool>,
+    library_hashes: Arc<Mutex<LibraryHashes>>,
+    outgoing_tx: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
+    output_tx: tokio::sync::broadcast::Sender<BuildOutputMessages>,
+    code_filter: CodeWatchFilter,
+}
+
+impl BuildEvents for CommandBuildEvents {
+    fn build(&mut self) {
+        if self.build_active.swap(true, Ordering::SeqCst) {
+            self.build_pending.store(true, Ordering::SeqCst);
+            return;
+        }
+        let _ = self.outgoing_tx.send(BuilderOutgoingMessages::BuildStarted);
+        let id = self.id.clone();
+        let build_active = self.build_active.clone();
+        let build_pending = self.build_pending.clone();
+        let library_hashes = self.library_hashes.clone();
+        let output_tx = self.output_tx.clone();
+        let settings = self.settings.clone();
+        let target = self.target.clone();
+        tokio::spawn(async move {
+            loop {
+                let id = id.fetch_add(1, Ordering::SeqCst);
+                if let Err(e) =
+                    run_build_command(&target, &settings, id, &library_hashes, &output_tx).await
+                {
+                    error!("Build Error - {id} {target} - {e}");
+                    let _ = output_tx.send(BuildOutputMessages::FailedBuild(e.to_string()));
+                }
+                if !build_pending.swap(false, Ordering::SeqCst) {
+                    break;
+                }
+            }
+            build_active.store(false, Ordering::SeqCst);
+        });
+    }
+
+    fn is_relevant(&mut self, paths: &[Utf8PathBuf]) -> bool {
+        self.code_filter.is_relevant(paths)
+    }
+}
+
+async fn run_build_command(
+    target: &Target,
+    settings: &TargetBuildSettings,
+    id: u32,
+    library_hashes: &Mutex<LibraryHashes>,
+    output_tx: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
+) -> anyhow::Result<()> {
+    let Some((program, args)) = settings.build_command.split_first() else {
+        bail!("No build command set");
+    };
+    info!("Command Build {id} Started - {:?}", settings.build_command);
+
+    let working_dir = match &settings.working_dir {
+        Some(dir) => dir.clone(),
+        None => Utf8PathBuf::try_from(std::env::current_dir()?)?,
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(&working_dir)
+        .envs(&settings.environment)
+        .env("DEXTEROUS_DEVELOPER_BUILD_ID", id.to_string())
+        .env("DEXTEROUS_DEVELOPER_TARGET", target.as_str())
+        .env("DEXTEROUS_DEVELOPER_PROFILE", settings.cargo_profile())
+        .env("DEXTEROUS_DEVELOPER_FEATURES", settings.features.join(","))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true);
+    if let Some(toolchain) = settings.toolchain.as_ref() {
+        command.env(TOOLCHAIN_VARIABLE, toolchain);
+    }
+
+    let _ = output_tx.send(BuildOutputMessages::StartedBuild(id));
+    let mut child = command.spawn()?;
+    let Some(stdout) = child.stdout.take() else {
+        bail!("Couldn't read the build command's output");
+    };
+
+    let mut libraries = vec![];
+    let mut root_library = None;
+    let mut failures = vec![];
+
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        match CommandBuildMessage::parse(&line) {
+            Some(CommandBuildMessage::Library { path }) => libraries.push(path),
+            Some(CommandBuildMessage::RootLibrary { path }) => {
+                if let Some(previous) = root_library.replace(path) {
+                    warn!(
+                        "Build command reported more than one root library - ignoring {previous}"
+                    );
+                }
+            }
+            Some(CommandBuildMessage::Failed { message }) => failures.push(message),
+            None => eprintln!("{line}"),
+        }
+    }
+
+    let status = child.wait().await?;
+    if !failures.is_empty() {
+        bail!("{}", failures.join("\n"));
+    }
+    if !status.success() {
+        bail!("Build command exited with {status}");
+    }
+    let Some(root_library) = root_library else {
+        bail!("Build command didn't report a root library");
+    };
+
+    let (root_name, changes) = hash_libraries(
+        &working_dir,
+        &root_library,
+        &libraries,
+        &mut *library_hashes.lock().await,
+    )?;
+    debug!("Unchanged libraries - {:?}", changes.unchanged);
+    let _ = output_tx.send(BuildOutputMessages::EndedBuild {
+        id,
+        libraries: changes.changed(),
+        unchanged: changes.unchanged.into_iter().collect(),
+        root_library: root_name,
+    });
+    info!("Build {id} Completed");
+    Ok(())
+}
+
+/// Hashes the reported libraries, works out which of them depend on each other,
+/// and returns them along with the root library's name
+fn hash_libraries(
+    working_dir: &Utf8Path,
+    root_library: &Utf8Path,
+    libraries: &[Utf8PathBuf],
+    library_hashes: &mut LibraryHashes,
+) -> anyhow::Result<(String, LibraryChanges)> {
+    let mut files = HashMap::new();
+    for path in libraries
+        .iter()
+        .map(|path| path.as_path())
+        .chain(std::iter::once(root_library))
+    {
+        let path = working_dir.join(path);
+        let Some(name) = path.file_name() else {
+            bail!("{path} isn't a file");
+        };
+        files.insert(name.to_string(), path);
+    }
+
+    let Some(root_name) = root_library.file_name() else {
+        bail!("{root_library} isn't a file");
+    };
+    let dependencies =
+        match DependencyGraph::resolve(root_name, &working_dir.join(root_library), &files) {
+            Ok(graph) => graph.dependencies,
+            Err(e) => {
+                warn!("Couldn't read the dependencies of {root_library} - {e}");
+                HashMap::new()
+            }
+        };
+
+    let changes = library_hashes.update(root_name, &files, &dependencies)?;
+    Ok((root_name.to_string(), changes))
+}
+
+impl Builder for CommandBuilder {
+    fn target(&self) -> Target {
+        self.target.clone()
+    }
+
+    fn builder_type(&self) -> BuilderTypes {
+        BuilderTypes::Command
+    }
+
+    fn outgoing_channel(
+        &self,
+    ) -> (
+        tokio::sync::broadcast::Receiver<BuilderOutgoingMessages>,
+        tokio::sync::broadcast::Receiver<BuildOutputMessages>,
+    ) {
+        (self.outgoing.subscribe(), self.output.subscribe())
+    }
+
+    fn root_lib_name(&self) -> Option<String> {
+        None
+    }
+
+    fn get_code_subscriptions(&self) -> Vec<Utf8PathBuf> {
+        self.settings.code_watch_folders.clone()
+    }
+
+    fn get_asset_subscriptions(&self) -> Vec<Utf8PathBuf> {
+        self.settings.asset_folders.clone()
+    }
+
+    fn last_completed_build(&self) -> Option<CompletedBuild> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use test_temp_dir::*;
+
+    use super::{hash_libraries, CommandBuildMessage};
+    use crate::default_builder::library_hashes::LibraryHashes;
+    #[cfg(unix)]
+    use crate::types::{BuildOutputMessages, Builder, BuilderIncomingMessages};
+    #[cfg(unix)]
+    use dexterous_developer_types::{Target, TargetBuildSettings};
+
+    /// Runs a shell script as the build command, and returns the messages up to the end of the first build
+    #[cfg(unix)]
+    async fn build_with_script(script: &str) -> Vec<BuildOutputMessages> {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("libgame.so"), "game").unwrap();
+
+        let target = Target::current().expect("Couldn't determine current target");
+        let (incoming, _) = tokio::sync::broadcast::channel(100);
+        let builder = super::CommandBuilder::new(
+            target.clone(),
+            TargetBuildSettings {
+                working_dir: Some(dir),
+                build_command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+                ..Default::default()
+            },
+            incoming.clone(),
+        )
+        .expect("Couldn't set up the command builder");
+        let (_, mut output) = builder.outgoing_channel();
+
+        incoming
+            .send(BuilderIncomingMessages::RequestBuild(target))
+            .expect("Failed to request build");
+
+        let mut messages = vec![];
+        tokio::time::timeout(std::time::Duration::from_secs(30), async {
+            loop {
+                let message = output.recv().await.expect("Couldn't get message");
+                let finished = matches!(
+                    message,
+                    BuildOutputMessages::EndedBuild { .. } | BuildOutputMessages::FailedBuild(_)
+                );
+                messages.push(message);
+                if finished {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("Build didn't finish in time");
+        messages
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_build_command_reporting_a_root_library_ends_the_build() {
+        let messages = build_with_script(
+            r#"echo "Generating code"; echo '{"type": "root_library", "path": "libgame.so"}'"#,
+        )
+        .await;
+
+        assert!(matches!(
+            messages.first(),
+            Some(BuildOutputMessages::StartedBuild(1))
+        ));
+        let Some(BuildOutputMessages::EndedBuild {
+            id,
+            libraries,
+            root_library,
+            ..
+        }) = messages.last()
+        else {
+            panic!("Build didn't end - {messages:?}");
+        };
+        assert_eq!(*id, 1);
+        assert_eq!(root_library, "libgame.so");
+        assert_eq!(libraries.len(), 1);
+        assert_eq!(libraries[0].hash, *blake3::hash(b"game").as_bytes());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_build_command_reporting_a_failure_fails_the_build() {
+        let messages = build_with_script(
+            r#"echo '{"type": "root_library", "path": "libgame.so"}'; echo '{"type": "failed", "message": "codegen broke"}'"#,
+        )
+        .await;
+
+        let Some(BuildOutputMessages::FailedBuild(message)) = messages.last() else {
+            panic!("Build didn't fail - {messages:?}");
+        };
+        assert_eq!(message, "codegen broke");
+
+        let messages = build_with_script("exit 3").await;
+        let Some(BuildOutputMessages::FailedBuild(message)) = messages.last() else {
+            panic!("Build didn't fail - {messages:?}");
+        };
+        assert!(message.contains("exit status: 3"), "{message}");
+    }
+
+    #[test]
+    fn parses_messages_and_ignores_other_output() {
+        assert_eq!(
+            CommandBuildMessage::parse(r#"{"type": "root_library", "path": "out/libgame.so"}"#),
+            Some(CommandBuildMessage::RootLibrary {
+                path: Utf8PathBuf::from("out/libgame.so")
+            })
+        );
+        assert_eq!(
+            CommandBuildMessage::parse(r#"  {"type":"library","path":"libdep.so"}"#),
+            Some(CommandBuildMessage::Library {
+                path: Utf8PathBuf::from("libdep.so")
+            })
+        );
+        assert_eq!(
+            CommandBuildMessage::parse(r#"{"type": "failed", "message": "codegen broke"}"#),
+            Some(CommandBuildMessage::Failed {
+                message: "codegen broke".to_string()
+            })
+        );
+        assert_eq!(CommandBuildMessage::parse("Compiling game v0.1.0"), None);
+        assert_eq!(CommandBuildMessage::parse(r#"{"unrelated": true}"#), None);
+    }
+
+    #[test]
+    fn builds_include_every_reported_library() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(dir.join("out")).unwrap();
+        std::fs::write(dir.join("out/libgame.so"), "game").unwrap();
+        std::fs::write(dir.join("out/libdep.so"), "dep").unwrap();
+
+        let (root_library, changes) = hash_libraries(
+            &dir,
+            &Utf8PathBuf::from("out/libgame.so"),
+            &[Utf8PathBuf::from("out/libdep.so")],
+            &mut LibraryHashes::default(),
+        )
+        .expect("Couldn't hash libraries");
+
+        assert_eq!(root_library, "libgame.so");
+        assert_eq!(changes.libraries.len(), 2);
+        let dependency = changes
+            .libraries
+            .iter()
+            .find(|library| library.relative_path == "./libdep.so")
+            .expect("Dependency wasn't included");
+        assert_eq!(dependency.local_path, dir.join("out/libdep.so"));
+        assert_eq!(dependency.hash, *blake3::hash(b"dep").as_bytes());
+    }
+}
@@ -1,45 +1,74 @@
-use cargo_metadata::Metadata;
-use debounced::debounced;
+use cargo_metadata::{DependencyKind, Metadata};
 use futures_util::future::join_all;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
-    env, fs,
+    env,
     process::Stdio,
     sync::{
         atomic::{AtomicBool, AtomicU32, Ordering},
         Arc,
     },
-    time::Duration,
+    time::Instant,
 };
-use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
 
 use anyhow::bail;
 
-use camino::{Utf8Path, Utf8PathBuf};
-use dexterous_developer_types::{cargo_path_utils::dylib_path, Target, TargetBuildSettings};
+use camino::Utf8PathBuf;
+use dexterous_developer_types::{
+    cargo_path_utils::dylib_path, profile_directory, BuildDiagnostic, BuildPhase, BuildProgress,
+    DiagnosticLevel, DiagnosticSpan, Linker, PackageOrExample, PhaseTiming, Target,
+    TargetBuildSettings,
+};
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::Command,
     select,
-    sync::Mutex,
+    sync::{Mutex, Notify},
     task::JoinHandle,
 };
-use tracing::{debug, error, info, trace};
-
-use crate::types::{
-    BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
-    BuilderOutgoingMessages, HashedFileRecord,
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    build_loop::{run_event_loop, BuildEvents},
+    default_builder::{
+        cleanup::{output_directory, remove_old_builds},
+        dylib_dependencies::DependencyGraph,
+        hot_crates::{hot_crate_directory, HOT_CRATES_VARIABLE},
+        library_hashes::LibraryHashes,
+        linker::linker_flag,
+        metadata_cache::CachedMetadata,
+        persisted_state::PersistedBuildState,
+        process_group::{isolate, ProcessGroup},
+        toolchain::{toolchain_library_directories, TOOLCHAIN_VARIABLE},
+    },
+    types::{
+        BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
+        BuilderOutgoingMessages, CompletedBuild,
+    },
+    watch_filter::CodeWatchFilter,
 };
 
 pub struct DefaultBuilderInitializer {
     target: Target,
     settings: TargetBuildSettings,
+    metadata: Option<Metadata>,
 }
 
 impl DefaultBuilderInitializer {
     pub fn new(target: Target, settings: TargetBuildSettings) -> Self {
-        Self { target, settings }
+        Self {
+            target,
+            settings,
+            metadata: None,
+        }
+    }
+
+    /// Starts the builder with `cargo metadata` that was already read for its workspace,
+    /// instead of running cargo again for the first build
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
     }
 }
 
@@ -50,22 +79,40 @@ impl BuilderInitializer for DefaultBuilderInitializer {
         self,
         channel: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
     ) -> anyhow::Result<Self::Inner> {
-        DefaultBuilder::new(self.target, self.settings, channel)
+        DefaultBuilder::new(self.target, self.settings, self.metadata, channel)
     }
 }
 
 pub struct DefaultBuilder {
     target: Target,
     settings: TargetBuildSettings,
+    code_filter: Arc<std::sync::RwLock<CodeWatchFilter>>,
     outgoing: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
     output: tokio::sync::broadcast::Sender<BuildOutputMessages>,
+    last_completed_build: Option<CompletedBuild>,
     #[allow(dead_code)]
     handle: tokio::task::JoinHandle<()>,
 }
 
 async fn build(
     target: Target,
-    TargetBuildSettings {
+    settings: TargetBuildSettings,
+    previous_versions: Arc<Mutex<Vec<(String, Utf8PathBuf)>>>,
+    sender: tokio::sync::broadcast::Sender<BuildOutputMessages>,
+    outgoing: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
+    id: u32,
+    cancel: Arc<Notify>,
+    previous_crate_count: Arc<AtomicU32>,
+    metadata_cache: Arc<Mutex<Option<CachedMetadata>>>,
+    library_hashes: Arc<Mutex<LibraryHashes>>,
+    loaded_builds: Arc<std::sync::Mutex<HashSet<u32>>>,
+    code_filter: Arc<std::sync::RwLock<CodeWatchFilter>>,
+    keep_builds: usize,
+) -> Result<(), anyhow::Error> {
+    let toolchain = settings
+        .rust_toolchain()
+        .map(|toolchain| toolchain.to_string());
+    let TargetBuildSettings {
         working_dir,
         package_or_example,
         features,
@@ -73,100 +120,77 @@ async fn build(
         additional_library_directories,
         apple_sdk_directory,
         craneflift,
+        profile,
+        linker,
+        hot_crates,
+        code_watch_folders,
+        ignore,
         ..
-    }: TargetBuildSettings,
-    previous_versions: Arc<Mutex<Vec<(String, Utf8PathBuf)>>>,
-    sender: tokio::sync::broadcast::Sender<BuildOutputMessages>,
-    id: u32,
-) -> Result<(), anyhow::Error> {
+    } = settings;
     info!("Default Build {id} Started");
+    let started = Instant::now();
+    let mut timings = Vec::with_capacity(6);
     eprintln!("Starting Builder");
 
-    let (artifact_name, artifact_file_name) = {
-        let mut cmd = Command::new("cargo");
-        cmd.arg("metadata");
-        if let Some(manifest_path) = &manifest_path {
-            cmd.arg("--manifest-path").arg(manifest_path);
-        }
-        if let Some(working_dir) = &working_dir {
-            cmd.current_dir(working_dir);
-        }
-
-        eprintln!("Requesting Cargo Metadata");
-        let output = cmd.output().await?;
-
-        eprintln!("Got Cargo Metadata");
-
-        if !output.status.success() {
-            bail!("Failed to get Cargo metadata");
+    let cached_metadata = metadata_cache
+        .lock()
+        .await
+        .clone()
+        .filter(|cached| cached.is_current());
+    let metadata = match cached_metadata {
+        Some(metadata) => {
+            eprintln!("Using Cached Cargo Metadata");
+            metadata
         }
-        let output: Metadata = serde_json::from_slice(&output.stdout)?;
-
-        match &package_or_example {
-            dexterous_developer_types::PackageOrExample::DefaulPackage => {
-                let Some(root) = (if let Some(package) = output.root_package() {
-                    find_package_target(package, target, id)
-                } else if output.workspace_default_members.len() == 1 {
-                    let default_member = output.workspace_default_members.first().unwrap();
-                    if let Some(package) = output.packages.iter().find(|p| p.id == *default_member)
-                    {
-                        find_package_target(package, target, id)
-                    } else {
-                        None
+        None => {
+            let metadata = read_metadata(
+                &package_or_example,
+                manifest_path.as_ref(),
+                working_dir.as_ref(),
+                toolchain.as_deref(),
+            )
+            .await?;
+            metadata_cache.lock().await.replace(metadata.clone());
+            if code_watch_folders.is_empty() {
+                match CodeWatchFilter::new(&metadata.code_watch_folders, &ignore) {
+                    Ok(filter) => {
+                        let changed = code_filter
+                            .read()
+                            .map(|current| current.folders() != filter.folders())
+                            .unwrap_or(true);
+                        if changed {
+                            info!(
+                                "No code watch folders configured for {target}, watching {:?}",
+                                filter.folders()
+                            );
+                            let folders = filter.folders().to_vec();
+                            if let Ok(mut current) = code_filter.write() {
+                                *current = filter;
+                            }
+                            let _ = outgoing.send(BuilderOutgoingMessages::WatchCode(folders));
+                        }
                     }
-                } else {
-                    None
-                }) else {
-                    bail!("Can't find default package target");
-                };
-                root
-            }
-            dexterous_developer_types::PackageOrExample::Package(package) => {
-                let Some(package) = output.packages.iter().find(|p| p.name == *package) else {
-                    let packages = output
-                        .packages
-                        .iter()
-                        .map(|v| v.name.to_string())
-                        .collect::<Vec<_>>();
-                    bail!("Couldn't find package - {package} - {packages:?}");
-                };
-                let Some(p) = find_package_target(package, target, id) else {
-                    bail!("Can't find package target");
-                };
-                p
-            }
-            dexterous_developer_types::PackageOrExample::Example(e) => {
-                let Some((example_target, package)) = output
-                    .packages
-                    .into_iter()
-                    .flat_map(|e| {
-                        e.targets
-                            .iter()
-                            .map(|t| (t.clone(), e.clone()))
-                            .collect::<Vec<_>>()
-                    })
-                    .find(|(t, _)| t.is_example() && t.name == *e)
-                else {
-                    bail!("No such example");
-                };
-
-                if manifest_path.is_none() {
-                    manifest_path = Some(package.manifest_path);
+                    Err(e) => warn!("Couldn't filter code changes for {target} - {e}"),
                 }
-                let artifact_name = example_target.name.clone();
-                let artifact_file_name = target.dynamic_lib_name(&format!("{artifact_name}.{id}"));
-                (artifact_name, artifact_file_name)
             }
+            metadata
         }
     };
+    timings.push(PhaseTiming::new(BuildPhase::Metadata, started.elapsed()));
+    if manifest_path.is_none() {
+        manifest_path = metadata.manifest_path;
+    }
+    let artifact_name = metadata.artifact_name;
+    let artifact_file_name = target.dynamic_lib_name(&format!("{artifact_name}.{id}"));
     eprintln!("Got Artifact Name and File");
     info!("Artifact Name: {artifact_name} File: {artifact_file_name}");
 
+    let profile = profile.unwrap_or_else(|| "dev".to_string());
+
     let default_run_settings = if id == 1 {
         DefaultRunParams::InitialRun
     } else {
-        let target_dir = Utf8PathBuf::from(format!("./target/hot-reload/{target}/{target}/debug"))
-            .canonicalize_utf8()?;
+        let target_dir = output_directory(&target, &profile).canonicalize_utf8()?;
         let deps = target_dir.join("deps");
         let examples = target_dir.join("examples");
         if !target_dir.exists() {
@@ -208,11 +232,26 @@ async fn build(
 
     let target_dir = Utf8PathBuf::from_path_buf(dunce::canonicalize(target_dir)?)
         .map_err(|e| anyhow::anyhow!("Can't convert to utf8 {e:?}"))?;
-    let default_out = target_dir.join(format!("{target}")).join("debug");
+    let default_out = target_dir
+        .join(format!("{target}"))
+        .join(profile_directory(&profile));
     let deps = default_out.join("deps");
     let examples = default_out.join("examples");
     let artifact_path = default_out.join(&artifact_file_name);
 
+    if previous_versions.lock().await.is_empty() {
+        match remove_old_builds(&default_out, 0, &HashSet::new()) {
+            Ok(removed) if !removed.is_empty() => {
+                info!(
+                    "Removed {} libraries left over from earlier sessions",
+                    removed.len()
+                );
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Couldn't clean up libraries from earlier sessions - {e}"),
+        }
+    }
+
     info!("Paths ready - {artifact_path}");
 
     let mut lib_directories = additional_library_directories.clone();
@@ -240,8 +279,8 @@ async fn build(
     }
 
     options.common.features = features;
-    options.message_format = vec!["json-render-diagnostics".to_string()];
-    options.profile = Some("dev".to_string());
+    options.message_format = vec!["json".to_string()];
+    options.profile = Some(profile.clone());
     options.target = vec![target.to_string()];
 
     let rustc = which::which("dexterous_developer_rustc_wrapper")?;
@@ -257,24 +296,30 @@ async fn build(
     let cargo = options.command();
     let mut cargo = tokio::process::Command::from(cargo);
 
-    if let Some(working_dir) = working_dir {
-        cargo.current_dir(&working_dir);
+    if let Some(working_dir) = working_dir.as_ref() {
+        cargo.current_dir(working_dir);
+    }
+    if let Some(toolchain) = toolchain.as_ref() {
+        info!("Building with the {toolchain} toolchain");
+        cargo.env(TOOLCHAIN_VARIABLE, toolchain);
     }
 
     let mut rust_flags = "-Cprefer-dynamic".to_owned();
 
-    if matches!(target, Target::Linux | Target::LinuxArm | Target::Windows) {
-        rust_flags = format!("{rust_flags} -Clink-arg=-fuse-ld=lld");
+    let linker = linker.unwrap_or_else(|| Linker::default_for(&target));
+    info!("Linking with {linker}");
+    if let Some(flag) = linker_flag(&linker) {
+        rust_flags = format!("{rust_flags} {flag}");
     }
     if craneflift {
         eprintln!("USING CRANELIFT");
         rust_flags = format!("{rust_flags} -Zcodegen-backend=cranelift -Copt-level=0");
+        let profile_env = format!("CARGO_PROFILE_{}", profile.to_uppercase().replace('-', "_"));
         cargo
-            .env("RUSTUP_TOOLCHAIN", "nightly")
-            .env("CARGO_PROFILE_DEV_CODEGEN_BACKEND", "cranelift")
-            .env("CARGO_PROFILE_DEV_OPT_LEVEL", "1")
-            .env("CARGO_PROFILE_DEV_PACKAGE_*_CODEGEN_BACKEND", "llvm")
-            .env("CARGO_PROFILE_DEV_PACKAGE_*_OPT_LEVEL", "3");
+            .env(format!("{profile_env}_CODEGEN_BACKEND"), "cranelift")
+            .env(format!("{profile_env}_OPT_LEVEL"), "1")
+            .env(format!("{profile_env}_PACKAGE_*_CODEGEN_BACKEND"), "llvm")
+            .env(format!("{profile_env}_PACKAGE_*_OPT_LEVEL"), "3");
     }
 
     cargo
@@ -291,21 +336,44 @@ async fn build(
             "DEXTEROUS_DEVELOPER_DEFAULT_RUN",
             serde_json::to_string(&default_run_settings)?,
         )
+        .env(HOT_CRATES_VARIABLE, serde_json::to_string(&hot_crates)?)
         .env("RUSTFLAGS", rust_flags);
 
     let _ = sender.send(BuildOutputMessages::StartedBuild(id));
     eprintln!("Started Compilation");
     info!("Ready to start build");
 
+    isolate(&mut cargo);
     let mut child = cargo
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()?;
+    let process_group = ProcessGroup::new(&child);
+    let compile_started = Instant::now();
+    // When the last crate other than the reloaded one finished - the rest of the build is spent on the reloaded crate
+    let mut dependencies_finished = compile_started;
+    let root_target_name = artifact_name.replace('-', "_");
 
     let mut succeeded = false;
 
     let mut artifacts = Vec::with_capacity(20);
 
+    let estimated_total = match previous_crate_count.load(Ordering::SeqCst) {
+        0 => None,
+        count => Some(count),
+    };
+    let progress = |current_crate: &str, compiled: usize, finished: bool| {
+        BuildOutputMessages::BuildProgress(BuildProgress {
+            build_id: id,
+            current_crate: current_crate.to_string(),
+            compiled: compiled as u32,
+            estimated_total,
+            elapsed: started.elapsed(),
+            finished,
+        })
+    };
+
     let Some(output) = child.stdout.take() else {
         bail!("No Std Out");
     };
@@ -323,37 +391,72 @@ async fn build(
 
     let mut out_reader = BufReader::new(output).lines();
 
-    while let Some(line) = out_reader.next_line().await? {
+    loop {
+        let line = select! {
+            line = out_reader.next_line() => line?,
+            _ = cancel.notified() => {
+                info!("Cancelling build {id}");
+                process_group.kill(&mut child).await;
+                let _ = sender.send(BuildOutputMessages::CancelledBuild(id));
+                return Ok(());
+            }
+        };
+        let Some(line) = line else {
+            break;
+        };
         trace!("Compiler Output: {line}");
         let message = serde_json::from_str(&line)?;
 
         match &message {
             cargo_metadata::Message::CompilerArtifact(artifact) => {
+                if artifact.target.name.replace('-', "_") != root_target_name {
+                    dependencies_finished = Instant::now();
+                }
                 artifacts.push(artifact.clone());
+                let _ = sender.send(progress(&artifact.target.name, artifacts.len(), false));
+            }
+            cargo_metadata::Message::CompilerMessage(message) => {
+                let diagnostic = convert_diagnostic(&message.message);
+                if let Some(rendered) = &diagnostic.rendered {
+                    eprint!("{rendered}");
+                }
+                let _ = sender.send(BuildOutputMessages::Diagnostics {
+                    id,
+                    diagnostics: vec![diagnostic],
+                });
             }
             cargo_metadata::Message::BuildFinished(finished) => {
                 info!("Build Finished: {finished:?}");
                 succeeded = finished.success;
+                let current_crate = artifacts
+                    .last()
+                    .map(|artifact| artifact.target.name.as_str())
+                    .unwrap_or_default();
+                let _ = sender.send(progress(current_crate, artifacts.len(), true));
+                if succeeded {
+                    previous_crate_count.store(artifacts.len() as u32, Ordering::SeqCst);
+                }
             }
             msg => trace!("Compiler: {msg:?}"),
         }
     }
 
     eprintln!("Build Completed");
+    timings.push(PhaseTiming::new(
+        BuildPhase::Dependencies,
+        dependencies_finished.duration_since(compile_started),
+    ));
+    timings.push(PhaseTiming::new(
+        BuildPhase::ReloadedCrate,
+        dependencies_finished.elapsed(),
+    ));
 
     if !succeeded {
         error!("Build Failed");
         bail!("Failed to build");
     }
 
-    let mut libraries = HashMap::<String, Utf8PathBuf>::with_capacity(20);
-    libraries.insert(artifact_file_name.clone(), artifact_path.clone());
-
-    let initial_libraries = libraries
-        .iter()
-        .map(|(name, path)| (name.clone(), path.clone()))
-        .collect::<Vec<_>>();
-
+    let scan_started = Instant::now();
     let mut path_var = match env::var_os("PATH") {
         Some(var) => env::split_paths(&var)
             .filter_map(|p| Utf8PathBuf::try_from(p).ok())
@@ -362,7 +465,7 @@ async fn build(
     };
 
     let mut dylib_paths = dylib_path();
-    let mut root_dirs = vec![default_out, deps, examples];
+    let mut root_dirs = vec![default_out.clone(), deps, examples];
 
     path_var.append(&mut dylib_paths);
     path_var.append(&mut root_dirs);
@@ -373,23 +476,16 @@ async fn build(
             .join("hot-reload")
             .join(target.to_string())
             .join(target.to_string())
-            .join("debug")
+            .join(profile_directory(&profile))
             .join("deps"),
     );
 
-    {
-        let rustup_home = home::rustup_home()?;
-        let toolchains = rustup_home.join("toolchains");
-
-        let mut dir = tokio::fs::read_dir(toolchains).await?;
-
-        while let Ok(Some(child)) = dir.next_entry().await {
-            if child.file_type().await?.is_dir() {
-                let path = Utf8PathBuf::from_path_buf(child.path()).unwrap_or_default();
-                path_var.push(path.join("lib"));
-            }
-        }
+    match toolchain_library_directories(toolchain.as_deref(), &target, working_dir.as_ref()).await {
+        Ok(mut directories) => path_var.append(&mut directories),
+        Err(e) => warn!("Couldn't search the toolchain for libraries - {e}"),
     }
+    // Last, so hot crates resolve to the copies that outlive newer versions in `deps`
+    path_var.push(hot_crate_directory(&default_out));
 
     trace!("Path Var for DyLib Search: {path_var:?}");
 
@@ -402,11 +498,12 @@ async fn build(
             let mut files = vec![];
 
             while let Ok(Some(child)) = dir.next_entry().await {
-                let Ok(file_type) = child.file_type().await else {
+                // Follows symlinks, since versioned sonames are usually links to the real library
+                let Ok(metadata) = tokio::fs::metadata(child.path()).await else {
                     continue;
                 };
 
-                if file_type.is_file() {
+                if metadata.is_file() {
                     let Ok(path) = Utf8PathBuf::from_path_buf(child.path()) else {
                         continue;
                     };
@@ -428,60 +525,181 @@ async fn build(
         .flatten()
         .cloned()
         .collect::<HashMap<_, _>>();
+    timings.push(PhaseTiming::new(
+        BuildPhase::ToolchainScan,
+        scan_started.elapsed(),
+    ));
 
-    let mut dependencies = HashMap::new();
-
-    for (name, library) in initial_libraries.iter() {
-        process_dependencies_recursive(
-            &searchable_files,
-            &mut libraries,
-            &mut dependencies,
-            name,
-            library,
-        )
-        .await?;
-    }
-
-    let libraries = {
-        libraries
-            .iter()
-            .map(|(library, local_path)| {
-                let file = std::fs::read(local_path)?;
-                let hash = blake3::hash(&file);
-
-                Ok(HashedFileRecord {
-                    name: library.clone(),
-                    local_path: local_path.clone(),
-                    relative_path: Utf8PathBuf::from(format!("./{library}")),
-                    hash: hash.as_bytes().to_owned(),
-                    dependencies: dependencies
-                        .get(library.as_str())
-                        .cloned()
-                        .unwrap_or_default(),
-                })
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?
-    };
+    let resolve_started = Instant::now();
+    let DependencyGraph {
+        libraries,
+        dependencies,
+    } = DependencyGraph::resolve(&artifact_file_name, &artifact_path, &searchable_files)?;
+    debug!("Dependency graph - {dependencies:?}");
+    timings.push(PhaseTiming::new(
+        BuildPhase::DependencyResolution,
+        resolve_started.elapsed(),
+    ));
+
+    let hashing_started = Instant::now();
+    let changes =
+        library_hashes
+            .lock()
+            .await
+            .update(&artifact_file_name, &libraries, &dependencies)?;
+    debug!("Unchanged libraries - {:?}", changes.unchanged);
+    timings.push(PhaseTiming::new(
+        BuildPhase::Hashing,
+        hashing_started.elapsed(),
+    ));
 
     {
         let mut previous = previous_versions.lock().await;
         previous.push((format!("{artifact_name}.{id}"), artifact_path.clone()));
+
+        let loaded = loaded_builds
+            .lock()
+            .map(|loaded| loaded.clone())
+            .unwrap_or_default();
+        for directory in [default_out.clone(), hot_crate_directory(&default_out)] {
+            match remove_old_builds(&directory, keep_builds, &loaded) {
+                Ok(removed) => trace!("Removed old libraries - {removed:?}"),
+                Err(e) => warn!("Couldn't remove old libraries - {e}"),
+            }
+        }
+        previous.retain(|(_, path)| path.exists());
+
+        let state = PersistedBuildState {
+            next_id: id + 1,
+            previous_versions: previous.clone(),
+            last_completed_build: Some(CompletedBuild {
+                id,
+                libraries: changes.libraries.clone(),
+                root_library: artifact_file_name.clone(),
+            }),
+        };
+        if let Err(e) = state.save(&default_out) {
+            warn!("Couldn't save the hot reload state - {e}");
+        }
     }
 
     let _ = sender.send(BuildOutputMessages::EndedBuild {
         id,
-        libraries,
+        libraries: changes.changed(),
+        unchanged: changes.unchanged.into_iter().collect(),
         root_library: artifact_file_name,
     });
+    debug!("Build {id} timings - {timings:?}");
+    let _ = sender.send(BuildOutputMessages::Timings { id, timings });
     info!("Build {id} Completed");
     Ok(())
 }
 
-fn find_package_target(
-    package: &cargo_metadata::Package,
-    target: Target,
-    id: u32,
-) -> Option<(String, String)> {
+/// Runs `cargo metadata` and finds the artifact to build
+async fn read_metadata(
+    package_or_example: &PackageOrExample,
+    manifest_path: Option<&Utf8PathBuf>,
+    working_dir: Option<&Utf8PathBuf>,
+    toolchain: Option<&str>,
+) -> anyhow::Result<CachedMetadata> {
+    let metadata = cargo_metadata(manifest_path, working_dir, toolchain).await?;
+    cache_metadata(&metadata, package_or_example)
+}
+
+/// Runs `cargo metadata`, including dependencies, for a workspace
+pub async fn cargo_metadata(
+    manifest_path: Option<&Utf8PathBuf>,
+    working_dir: Option<&Utf8PathBuf>,
+    toolchain: Option<&str>,
+) -> anyhow::Result<Metadata> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("metadata");
+    if let Some(toolchain) = toolchain {
+        cmd.env(TOOLCHAIN_VARIABLE, toolchain);
+    }
+    if let Some(manifest_path) = manifest_path {
+        cmd.arg("--manifest-path").arg(manifest_path);
+    }
+    if let Some(working_dir) = working_dir {
+        cmd.current_dir(working_dir);
+    }
+
+    eprintln!("Requesting Cargo Metadata");
+    let output = cmd.output().await?;
+
+    eprintln!("Got Cargo Metadata");
+
+    if !output.status.success() {
+        bail!("Failed to get Cargo metadata");
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Picks out the parts of `cargo metadata` a build of the package or example needs
+fn cache_metadata(
+    output: &Metadata,
+    package_or_example: &PackageOrExample,
+) -> anyhow::Result<CachedMetadata> {
+    let artifact_name = match package_or_example {
+        dexterous_developer_types::PackageOrExample::DefaulPackage => {
+            let Some(root) = (if let Some(package) = output.root_package() {
+                find_package_target(package)
+            } else if output.workspace_default_members.len() == 1 {
+                let default_member = output.workspace_default_members.first().unwrap();
+                if let Some(package) = output.packages.iter().find(|p| p.id == *default_member) {
+                    find_package_target(package)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }) else {
+                bail!("Can't find default package target");
+            };
+            root
+        }
+        dexterous_developer_types::PackageOrExample::Package(package) => {
+            let Some(package) = output.packages.iter().find(|p| p.name == *package) else {
+                let packages = output
+                    .packages
+                    .iter()
+                    .map(|v| v.name.to_string())
+                    .collect::<Vec<_>>();
+                bail!("Couldn't find package - {package} - {packages:?}");
+            };
+            let Some(p) = find_package_target(package) else {
+                bail!("Can't find package target");
+            };
+            p
+        }
+        dexterous_developer_types::PackageOrExample::Example(e) => {
+            let Some((example_target, package)) = output
+                .packages
+                .iter()
+                .flat_map(|p| p.targets.iter().map(move |t| (t, p)))
+                .find(|(t, _)| t.is_example() && t.name == *e)
+            else {
+                bail!("No such example");
+            };
+
+            return Ok(CachedMetadata::new(
+                output,
+                example_target.name.clone(),
+                Some(package.manifest_path.clone()),
+                derive_code_watch_folders(output, package_or_example),
+            ));
+        }
+    };
+
+    Ok(CachedMetadata::new(
+        output,
+        artifact_name,
+        None,
+        derive_code_watch_folders(output, package_or_example),
+    ))
+}
+
+fn find_package_target(package: &cargo_metadata::Package) -> Option<String> {
     let targets = &package.targets;
 
     let package_target = if let Some(lib) = targets.iter().find(|target| target.is_lib()) {
@@ -490,230 +708,363 @@ fn find_package_target(
         targets
             .iter()
             .find(|target| target.is_bin() && &target.name == default_run)?
-    } else if let Some(first_bin) = targets.iter().find(|target| target.is_bin()) {
-        first_bin
     } else {
-        return None;
+        targets.iter().find(|target| target.is_bin())?
     };
 
-    let artifact_name = package_target.name.clone();
-    let artifact_file_name = target.dynamic_lib_name(&format!("{artifact_name}.{id}"));
-
-    Some((artifact_name, artifact_file_name))
+    Some(package_target.name.clone())
 }
 
-async fn process_dependencies_recursive(
-    searchable_files: &HashMap<String, Utf8PathBuf>,
-    libraries: &mut HashMap<String, Utf8PathBuf>,
-    dependencies: &mut HashMap<String, Vec<String>>,
-    current_library_name: &str,
-    current_library: &Utf8Path,
-) -> Result<(), anyhow::Error> {
-    eprintln!("Checking current library {current_library_name} {current_library}");
-    let file = fs::read(current_library)?;
-    let file = goblin::Object::parse(&file)?;
-    eprintln!("Parsed current library");
-
-    let dependency_vec = match file {
-        goblin::Object::Elf(elf) => {
-            let str_table = elf.dynstrtab;
-            elf.dynamic
-                .map(|dynamic| {
-                    dynamic
-                        .get_libraries(&str_table)
-                        .iter()
-                        .map(|v| v.to_string())
-                        .collect()
-                })
-                .unwrap_or_default()
-        }
-        goblin::Object::PE(pe) => pe
-            .libraries
+fn convert_diagnostic(diagnostic: &cargo_metadata::diagnostic::Diagnostic) -> BuildDiagnostic {
+    use cargo_metadata::diagnostic::DiagnosticLevel as CargoLevel;
+
+    let level = match diagnostic.level {
+        CargoLevel::Ice | CargoLevel::Error => DiagnosticLevel::Error,
+        CargoLevel::Warning => DiagnosticLevel::Warning,
+        CargoLevel::Note => DiagnosticLevel::Note,
+        CargoLevel::Help => DiagnosticLevel::Help,
+        _ => DiagnosticLevel::Other,
+    };
+
+    BuildDiagnostic {
+        level,
+        message: diagnostic.message.clone(),
+        code: diagnostic.code.as_ref().map(|code| code.code.clone()),
+        spans: diagnostic
+            .spans
             .iter()
-            .map(|import| import.to_string())
+            .map(|span| DiagnosticSpan {
+                file: Utf8PathBuf::from(&span.file_name),
+                line_start: span.line_start,
+                line_end: span.line_end,
+                column_start: span.column_start,
+                column_end: span.column_end,
+                is_primary: span.is_primary,
+                label: span.label.clone(),
+            })
             .collect(),
-        goblin::Object::Mach(mach) => match mach {
-            goblin::mach::Mach::Fat(fat) => {
-                let mut vec = HashSet::new();
-                while let Some(Ok(goblin::mach::SingleArch::MachO(arch))) = fat.into_iter().next() {
-                    let imports = arch.imports()?;
-                    let inner = imports.iter().map(|v| v.dylib.to_string());
-                    vec.extend(inner);
+        rendered: diagnostic.rendered.clone(),
+    }
+}
+
+/// Determines which folders to watch for a package or example when none are configured.
+///
+/// This includes the source directories of the package and of any path dependencies within
+/// the workspace, along with their manifests.
+fn derive_code_watch_folders(
+    metadata: &Metadata,
+    package_or_example: &PackageOrExample,
+) -> Vec<Utf8PathBuf> {
+    let (package, selected_target) = match package_or_example {
+        PackageOrExample::DefaulPackage => {
+            let package = metadata.root_package().or_else(|| {
+                if metadata.workspace_default_members.len() != 1 {
+                    return None;
                 }
-                vec
-            }
-            goblin::mach::Mach::Binary(std) => {
-                std.imports()?.iter().map(|v| v.dylib.to_string()).collect()
-            }
-        },
-        _ => HashSet::default(),
+                let default_member = metadata.workspace_default_members.first()?;
+                metadata.packages.iter().find(|p| p.id == *default_member)
+            });
+            (package, None)
+        }
+        PackageOrExample::Package(name) => {
+            (metadata.packages.iter().find(|p| p.name == *name), None)
+        }
+        PackageOrExample::Example(example) => metadata
+            .packages
+            .iter()
+            .find_map(|p| {
+                p.targets
+                    .iter()
+                    .find(|t| t.is_example() && t.name == *example)
+                    .map(|t| (Some(p), Some(t)))
+            })
+            .unwrap_or_default(),
+    };
+    let Some(package) = package else {
+        return vec![];
     };
 
-    eprintln!("Dependencies: {dependency_vec:?}");
+    let mut folders = vec![];
+    let mut visited = HashSet::new();
+    let mut pending = vec![package];
 
-    for library_name in dependency_vec.iter() {
-        if library_name.is_empty() {
+    while let Some(package) = pending.pop() {
+        if !visited.insert(&package.id) {
             continue;
         }
-        if libraries.contains_key(library_name) {
-            continue;
-        }
-        let Some(library_path) = searchable_files.get(library_name) else {
-            debug!("Couldn't find library with name {library_name}");
+        let Some(package_directory) = package.manifest_path.parent() else {
             continue;
         };
-        libraries.insert(library_name.to_string(), library_path.clone());
+
+        folders.push(package.manifest_path.clone());
+        let src = package_directory.join("src");
+        if src.exists() {
+            folders.push(src);
+        }
+
+        let is_root = visited.len() == 1;
+        for target in package.targets.iter().filter(|t| {
+            t.is_lib() || (is_root && selected_target.map(|s| s == *t).unwrap_or(t.is_bin()))
+        }) {
+            let Some(parent) = target.src_path.parent() else {
+                continue;
+            };
+            if parent == package_directory.join("examples") {
+                folders.push(target.src_path.clone());
+            } else {
+                folders.push(parent.to_owned());
+            }
+        }
+
+        for dependency in &package.dependencies {
+            if dependency.kind == DependencyKind::Development {
+                continue;
+            }
+            let Some(path) = &dependency.path else {
+                continue;
+            };
+            if !path.starts_with(&metadata.workspace_root) {
+                continue;
+            }
+            if let Some(dependency) = metadata
+                .packages
+                .iter()
+                .find(|p| p.manifest_path.parent() == Some(path.as_path()))
+            {
+                pending.push(dependency);
+            }
+        }
     }
-    dependencies.insert(
-        current_library_name.to_string(),
-        dependency_vec.into_iter().collect(),
-    );
-    Ok(())
+
+    let mut unique = vec![];
+    for folder in folders {
+        if !unique
+            .iter()
+            .any(|existing: &Utf8PathBuf| folder.starts_with(existing))
+        {
+            unique.retain(|existing: &Utf8PathBuf| !existing.starts_with(&folder));
+            unique.push(folder);
+        }
+    }
+    unique
 }
 
 impl DefaultBuilder {
     pub fn new(
         target: Target,
         settings: TargetBuildSettings,
+        metadata: Option<Metadata>,
         incoming: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
     ) -> anyhow::Result<Self> {
-        let mut incoming_rx = incoming.subscribe();
+        let incoming_rx = incoming.subscribe();
         let (outgoing_tx, _) = tokio::sync::broadcast::channel(100);
         let (output_tx, _) = tokio::sync::broadcast::channel(100);
-        let id = Arc::new(AtomicU32::new(1));
+        let persisted =
+            PersistedBuildState::load(&output_directory(&target, settings.cargo_profile()))
+                .unwrap_or_default();
+        if persisted.next_id > 1 {
+            info!(
+                "Continuing {target} builds from build {}",
+                persisted.next_id
+            );
+        }
+        let id = Arc::new(AtomicU32::new(persisted.next_id.max(1)));
         let build_active = Arc::new(AtomicBool::new(false));
         let build_pending = Arc::new(AtomicBool::new(false));
-        let previous_versions = Arc::new(Mutex::new(vec![]));
-
-        let handle = {
-            let outgoing_tx = outgoing_tx.clone();
-            let output_tx = output_tx.clone();
-            let settings = settings.clone();
-            let id = id.clone();
-            tokio::spawn(async move {
-                let delay = Duration::from_secs(1);
-
-                let (build_trigger, build_trigger_rx) =
-                    tokio::sync::mpsc::unbounded_channel::<()>();
-
-                let stream = UnboundedReceiverStream::new(build_trigger_rx);
-
-                let mut debounced = debounced(stream, delay);
-                let first_build_triggered = Arc::new(AtomicBool::new(false));
-
-                loop {
-                    select! {
-                        Some(()) = debounced.next() => {
-                            if first_build_triggered.load(Ordering::SeqCst) {
-                                trigger_build(
-                                    &build_active,
-                                    &build_pending,
-                                    &id,
-                                    &outgoing_tx,
-                                    target,
-                                    &settings,
-                                    &output_tx,
-                                    &previous_versions,
-                                );
-                            } else {
-                                info!("Not building {target} yet");
-                            }
-                        }
-                        Ok(recv) = incoming_rx.recv() => {
-                            match recv {
-                                BuilderIncomingMessages::RequestBuild(request) => {
-                                    if target == request {
-                                        info!("Build Request");
-                                        first_build_triggered.store(true, Ordering::SeqCst);
-                                        let _ = build_trigger.send(());
-                                    }
-                                }
-                                BuilderIncomingMessages::CodeChanged => {
-                                    info!("Code Changed");
-                                    let _ = build_trigger.send(());
-                                }
-                                BuilderIncomingMessages::AssetChanged(asset) => {
-                                    trace!("Builder Received Asset Change - {asset:?}");
-                                    let _ = output_tx.send(BuildOutputMessages::AssetUpdated(asset));
-                                }
-                            }
-                        }
-                        else => { break }
-                    };
-                }
-            })
+        let current_build = Arc::new(std::sync::Mutex::new(None));
+        let previous_versions = Arc::new(Mutex::new(persisted.previous_versions));
+        let last_completed_build = persisted.last_completed_build;
+        let previous_crate_count = Arc::new(AtomicU32::new(0));
+        let cached_metadata = metadata.and_then(|metadata| {
+            cache_metadata(&metadata, &settings.package_or_example)
+                .map_err(|e| warn!("Couldn't use the cargo metadata for {target} - {e}"))
+                .ok()
+        });
+        let code_watch_folders = match &cached_metadata {
+            Some(cached) if settings.code_watch_folders.is_empty() => &cached.code_watch_folders,
+            _ => &settings.code_watch_folders,
         };
+        let code_filter = Arc::new(std::sync::RwLock::new(CodeWatchFilter::new(
+            code_watch_folders,
+            &settings.ignore,
+        )?));
+        let metadata_cache = Arc::new(Mutex::new(cached_metadata));
+        let library_hashes = Arc::new(Mutex::new(
+            last_completed_build
+                .as_ref()
+                .map(LibraryHashes::from_completed_build)
+                .unwrap_or_default(),
+        ));
+        let loaded_builds = Arc::new(std::sync::Mutex::new(HashSet::new()));
+
+        let handle = tokio::spawn(run_event_loop(
+            target.clone(),
+            incoming_rx,
+            output_tx.clone(),
+            DefaultBuildEvents {
+                build_active,
+                build_pending,
+                current_build,
+                id,
+                outgoing_tx: outgoing_tx.clone(),
+                target: target.clone(),
+                settings: settings.clone(),
+                output_tx: output_tx.clone(),
+                previous_versions,
+                previous_crate_count,
+                metadata_cache,
+                library_hashes,
+                loaded_builds,
+                code_filter: code_filter.clone(),
+            },
+        ));
 
         Ok(Self {
             settings,
+            code_filter,
             target,
             outgoing: outgoing_tx,
             output: output_tx,
+            last_completed_build,
             handle,
         })
     }
 }
 
+/// The state the default builder's event loop shares with its builds
+struct DefaultBuildEvents {
+    build_active: Arc<AtomicBool>,
+    build_pending: Arc<AtomicBool>,
+    current_build: Arc<std::sync::Mutex<Option<Arc<Notify>>>>,
+    id: Arc<AtomicU32>,
+    outgoing_tx: tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
+    target: Target,
+    settings: TargetBuildSettings,
+    output_tx: tokio::sync::broadcast::Sender<BuildOutputMessages>,
+    previous_versions: Arc<Mutex<Vec<(String, Utf8PathBuf)>>>,
+    previous_crate_count: Arc<AtomicU32>,
+    metadata_cache: Arc<Mutex<Option<CachedMetadata>>>,
+    library_hashes: Arc<Mutex<LibraryHashes>>,
+    loaded_builds: Arc<std::sync::Mutex<HashSet<u32>>>,
+    /// Without configured folders, builds replace this with the folders derived from cargo metadata
+    code_filter: Arc<std::sync::RwLock<CodeWatchFilter>>,
+}
+
+impl BuildEvents for DefaultBuildEvents {
+    fn build(&mut self) {
+        trigger_build(
+            &self.build_active,
+            &self.build_pending,
+            &self.current_build,
+            &self.id,
+            &self.outgoing_tx,
+            &self.target,
+            &self.settings,
+            &self.output_tx,
+            &self.previous_versions,
+            &self.previous_crate_count,
+            &self.metadata_cache,
+            &self.library_hashes,
+            &self.loaded_builds,
+            &self.code_filter,
+        );
+    }
+
+    fn is_relevant(&mut self, paths: &[Utf8PathBuf]) -> bool {
+        self.code_filter
+            .read()
+            .map(|filter| filter.is_relevant(paths))
+            .unwrap_or(true)
+    }
+
+    fn loaded_builds(&mut self, build_ids: HashSet<u32>) {
+        if let Ok(mut loaded) = self.loaded_builds.lock() {
+            *loaded = build_ids;
+        }
+    }
+}
+
 fn trigger_build(
     build_active: &Arc<AtomicBool>,
     build_pending: &Arc<AtomicBool>,
+    current_build: &Arc<std::sync::Mutex<Option<Arc<Notify>>>>,
     id: &Arc<AtomicU32>,
     outgoing_tx: &tokio::sync::broadcast::Sender<BuilderOutgoingMessages>,
-    target: Target,
+    target: &Target,
     settings: &TargetBuildSettings,
     output_tx: &tokio::sync::broadcast::Sender<BuildOutputMessages>,
     previous_versions: &Arc<Mutex<Vec<(String, Utf8PathBuf)>>>,
+    previous_crate_count: &Arc<AtomicU32>,
+    metadata_cache: &Arc<Mutex<Option<CachedMetadata>>>,
+    library_hashes: &Arc<Mutex<LibraryHashes>>,
+    loaded_builds: &Arc<std::sync::Mutex<HashSet<u32>>>,
+    code_filter: &Arc<std::sync::RwLock<CodeWatchFilter>>,
 ) {
     trace!("Triggering Build");
     let previous = build_active.swap(true, std::sync::atomic::Ordering::SeqCst);
     if previous {
         build_pending.store(true, std::sync::atomic::Ordering::SeqCst);
+        if settings.cancel_stale_builds {
+            if let Some(cancel) = current_build.lock().ok().and_then(|c| c.clone()) {
+                info!("Cancelling the running build for {target}");
+                cancel.notify_one();
+            }
+        }
     } else {
-        let id = id.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let _ = outgoing_tx.send(BuilderOutgoingMessages::BuildStarted);
+        let outgoing_tx = outgoing_tx.clone();
         let output_tx = output_tx.clone();
         let settings = settings.clone();
         let build_pending = build_pending.clone();
         let build_active = build_active.clone();
+        let current_build = current_build.clone();
+        let id_counter = id.clone();
         let previous_versions = previous_versions.clone();
+        let previous_crate_count = previous_crate_count.clone();
+        let metadata_cache = metadata_cache.clone();
+        let library_hashes = library_hashes.clone();
+        let loaded_builds = loaded_builds.clone();
+        let code_filter = code_filter.clone();
+        let target = target.clone();
         #[allow(clippy::let_underscore_future)]
         let _: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-            build(
-                target,
-                settings.clone(),
-                previous_versions.clone(),
-                output_tx.clone(),
-                id,
-            )
-            .await
-            .map_err(|e| {
-                error!("Build Error - {id} {target} - {e}");
-                let _ = output_tx.send(BuildOutputMessages::FailedBuild(e.to_string()));
-                build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
-                e
-            })?;
-
             loop {
+                let id = id_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let cancel = Arc::new(Notify::new());
+                if let Ok(mut current_build) = current_build.lock() {
+                    *current_build = Some(cancel.clone());
+                }
+                build(
+                    target.clone(),
+                    settings.clone(),
+                    previous_versions.clone(),
+                    output_tx.clone(),
+                    outgoing_tx.clone(),
+                    id,
+                    cancel,
+                    previous_crate_count.clone(),
+                    metadata_cache.clone(),
+                    library_hashes.clone(),
+                    loaded_builds.clone(),
+                    code_filter.clone(),
+                    settings.builds_to_keep(),
+                )
+                .await
+                .map_err(|e| {
+                    error!("Build Error - {id} {target} - {e}");
+                    let _ = output_tx.send(BuildOutputMessages::FailedBuild(e.to_string()));
+                    build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
+                    e
+                })?;
+
                 let pending = build_pending.swap(false, std::sync::atomic::Ordering::SeqCst);
-                if pending {
-                    build(
-                        target,
-                        settings.clone(),
-                        previous_versions.clone(),
-                        output_tx.clone(),
-                        id,
-                    )
-                    .await
-                    .map_err(|e| {
-                        error!("Build Error - {id} {target} - {e}");
-                        let _ = output_tx.send(BuildOutputMessages::FailedBuild(e.to_string()));
-                        build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
-                        e
-                    })?;
-                } else {
+                if !pending {
                     break;
                 }
             }
+            if let Ok(mut current_build) = current_build.lock() {
+                *current_build = None;
+            }
             build_active.swap(false, std::sync::atomic::Ordering::SeqCst);
             Ok(())
         });
@@ -722,7 +1073,7 @@ fn trigger_build(
 
 impl Builder for DefaultBuilder {
     fn target(&self) -> Target {
-        self.target
+        self.target.clone()
     }
 
     fn outgoing_channel(
@@ -739,13 +1090,20 @@ impl Builder for DefaultBuilder {
     }
 
     fn get_code_subscriptions(&self) -> Vec<camino::Utf8PathBuf> {
-        self.settings.code_watch_folders.clone()
+        self.code_filter
+            .read()
+            .map(|filter| filter.folders().to_vec())
+            .unwrap_or_else(|_| self.settings.code_watch_folders.clone())
     }
 
     fn get_asset_subscriptions(&self) -> Vec<camino::Utf8PathBuf> {
         self.settings.asset_folders.clone()
     }
 
+    fn last_completed_build(&self) -> Option<CompletedBuild> {
+        self.last_completed_build.clone()
+    }
+
     fn builder_type(&self) -> dexterous_developer_types::BuilderTypes {
         dexterous_developer_types::BuilderTypes::Default
     }
@@ -766,14 +1124,102 @@ mod test {
     use std::time::Duration;
 
     use super::*;
+    use crate::types::HashedFileRecord;
+    use cargo_metadata::MetadataCommand;
     use dexterous_developer_types::PackageOrExample;
     use test_temp_dir::*;
 
     use tokio::process::Command;
     use tokio::time::timeout;
 
+    /// The builds share `./target/hot-reload` and the state saved in it, so they can't run at the same time
+    static BUILD_LOCK: Mutex<()> = Mutex::const_new(());
+
+    #[test]
+    fn derives_code_watch_folders_from_package_and_path_dependencies() {
+        let metadata = MetadataCommand::new()
+            .no_deps()
+            .exec()
+            .expect("Couldn't get cargo metadata");
+        let workspace = &metadata.workspace_root;
+
+        let folders = derive_code_watch_folders(
+            &metadata,
+            &PackageOrExample::Package("dexterous_developer_builder".to_string()),
+        );
+
+        assert!(folders.contains(&workspace.join("dexterous_developer_builder/src")));
+        assert!(folders.contains(&workspace.join("dexterous_developer_builder/Cargo.toml")));
+        assert!(folders.contains(&workspace.join("dexterous_developer_types/src")));
+        assert!(!folders
+            .iter()
+            .any(|folder| folder.starts_with(workspace.join("dexterous_developer_manager"))));
+    }
+
+    #[tokio::test]
+    async fn derives_code_subscriptions_from_provided_metadata() {
+        let metadata = MetadataCommand::new()
+            .no_deps()
+            .exec()
+            .expect("Couldn't get cargo metadata");
+        let src = metadata
+            .workspace_root
+            .join("dexterous_developer_builder/src")
+            .canonicalize_utf8()
+            .unwrap();
+        let (incoming, _) = tokio::sync::broadcast::channel(100);
+
+        let build = DefaultBuilder::new(
+            Target::current().expect("Couldn't determine current target"),
+            TargetBuildSettings {
+                package_or_example: PackageOrExample::Package(
+                    "dexterous_developer_builder".to_string(),
+                ),
+                ..Default::default()
+            },
+            Some(metadata),
+            incoming,
+        )
+        .expect("Couldn't set up default builder");
+
+        assert!(build.get_code_subscriptions().contains(&src));
+    }
+
+    #[test]
+    fn converts_compiler_messages_into_diagnostics() {
+        let line = r#"{"reason":"compiler-message","package_id":"test_lib 0.1.0 (path+file:///test_lib)","manifest_path":"/test_lib/Cargo.toml","target":{"kind":["dylib"],"crate_types":["dylib"],"name":"test_lib","src_path":"/test_lib/src/lib.rs","edition":"2021","doc":true,"doctest":false,"test":true},"message":{"rendered":"error[E0308]: mismatched types\n","code":{"code":"E0308","explanation":null},"level":"error","message":"mismatched types","spans":[{"file_name":"src/lib.rs","byte_start":10,"byte_end":14,"line_start":2,"line_end":2,"column_start":5,"column_end":9,"is_primary":true,"text":[],"label":"expected `u32`, found `&str`","suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[]}}"#;
+
+        let cargo_metadata::Message::CompilerMessage(message) =
+            serde_json::from_str(line).expect("Couldn't parse message")
+        else {
+            panic!("Not a compiler message");
+        };
+        let diagnostic = convert_diagnostic(&message.message);
+
+        assert_eq!(diagnostic.level, DiagnosticLevel::Error);
+        assert_eq!(diagnostic.message, "mismatched types");
+        assert_eq!(diagnostic.code.as_deref(), Some("E0308"));
+        assert_eq!(
+            diagnostic.rendered.as_deref(),
+            Some("error[E0308]: mismatched types\n")
+        );
+        assert_eq!(
+            diagnostic.spans,
+            vec![DiagnosticSpan {
+                file: Utf8PathBuf::from("src/lib.rs"),
+                line_start: 2,
+                line_end: 2,
+                column_start: 5,
+                column_end: 9,
+                is_primary: true,
+                label: Some("expected `u32`, found `&str`".to_string()),
+            }]
+        );
+    }
+
     #[tokio::test]
     async fn can_build_a_package() {
+        let _lock = BUILD_LOCK.lock().await;
         let dir = test_temp_dir!();
         let dir_path = dir.as_path_untracked().to_path_buf();
 
@@ -788,18 +1234,17 @@ mod test {
 
         let target = Target::current().expect("Couldn't determine current target");
         let (incoming, _) = tokio::sync::broadcast::channel(100);
+        PersistedBuildState::clear(&output_directory(&target, "dev"))
+            .expect("Couldn't clear the previous build state");
 
         let build = DefaultBuilder::new(
-            target,
+            target.clone(),
             TargetBuildSettings {
                 package_or_example: PackageOrExample::Package("test_lib".to_string()),
                 working_dir: Utf8PathBuf::from_path_buf(dir_path).ok(),
-                code_watch_folders: vec![Utf8PathBuf::from_path_buf(
-                    dir.as_path_untracked().join("src"),
-                )
-                .unwrap()],
                 ..Default::default()
             },
+            None,
             incoming.clone(),
         )
         .expect("Couldn't set up default builder");
@@ -807,7 +1252,7 @@ mod test {
         let (mut builder_messages, mut build_messages) = build.outgoing_channel();
 
         incoming
-            .send(BuilderIncomingMessages::RequestBuild(target))
+            .send(BuilderIncomingMessages::RequestBuild(target.clone()))
             .expect("Failed to request build");
 
         let msg = timeout(Duration::from_secs(10), builder_messages.recv())
@@ -817,6 +1262,19 @@ mod test {
 
         assert!(matches!(msg, BuilderOutgoingMessages::BuildStarted));
 
+        let msg = timeout(Duration::from_secs(30), builder_messages.recv())
+            .await
+            .expect("Didn't recieve the code watch folders on time")
+            .expect("Didn't recieve the code watch folders");
+        let BuilderOutgoingMessages::WatchCode(folders) = msg else {
+            panic!("Expected the derived code watch folders, got {msg:?}");
+        };
+        let src = Utf8PathBuf::from_path_buf(dir.as_path_untracked().join("src")).unwrap();
+        assert!(folders
+            .iter()
+            .any(|folder| folder.canonicalize_utf8().ok() == src.canonicalize_utf8().ok()));
+        assert_eq!(build.get_code_subscriptions(), folders);
+
         let mut started = false;
         let mut ended = false;
         let mut root_lib_confirmed = false;
@@ -846,6 +1304,7 @@ mod test {
                         id,
                         libraries,
                         root_library,
+                        ..
                     } => {
                         eprintln!("Build {id} Ended");
                         if id != 1 {
@@ -879,6 +1338,10 @@ mod test {
                     BuildOutputMessages::AssetUpdated(_) => {}
                     BuildOutputMessages::KeepAlive => {}
                     BuildOutputMessages::FailedBuild(e) => bail!("Failed Build - {e}"),
+                    BuildOutputMessages::CancelledBuild(id) => bail!("Cancelled Build - {id}"),
+                    BuildOutputMessages::Diagnostics { .. } => {}
+                    BuildOutputMessages::BuildProgress(_) => {}
+                    BuildOutputMessages::Timings { .. } => {}
                 }
             }
             Ok(())
@@ -896,4 +1359,96 @@ mod test {
         assert!(root_lib_confirmed);
         assert!(library_update_received);
     }
+
+    #[tokio::test]
+    async fn cancels_a_stale_build_and_its_compiler_processes() {
+        let _lock = BUILD_LOCK.lock().await;
+        let dir = test_temp_dir!();
+        let dir_path = dir.as_path_untracked().to_path_buf();
+        let _ = Command::new("cargo")
+            .current_dir(&dir_path)
+            .arg("init")
+            .arg("--name=slow_lib")
+            .arg("--vcs=none")
+            .output()
+            .await
+            .expect("Failed to create test project");
+
+        // The build script takes a while the first time, and marks when it starts and whether it ran to the end
+        let slow = dir_path.join("slow");
+        let started_marker = dir_path.join("started");
+        let finished_marker = dir_path.join("finished");
+        std::fs::write(&slow, "").unwrap();
+        std::fs::write(
+            dir_path.join("build.rs"),
+            format!(
+                r#"fn main() {{
+    if std::path::Path::new({slow:?}).exists() {{
+        std::fs::write({started_marker:?}, "").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(8));
+        std::fs::write({finished_marker:?}, "").unwrap();
+    }}
+}}
+"#
+            ),
+        )
+        .unwrap();
+
+        let target = Target::current().expect("Couldn't determine current target");
+        let (incoming, _) = tokio::sync::broadcast::channel(100);
+        let build = DefaultBuilder::new(
+            target.clone(),
+            TargetBuildSettings {
+                package_or_example: PackageOrExample::Package("slow_lib".to_string()),
+                working_dir: Utf8PathBuf::from_path_buf(dir_path.clone()).ok(),
+                code_watch_folders: vec![Utf8PathBuf::from_path_buf(dir_path.join("src")).unwrap()],
+                cancel_stale_builds: true,
+                ..Default::default()
+            },
+            None,
+            incoming.clone(),
+        )
+        .expect("Couldn't set up default builder");
+        let (_, mut build_messages) = build.outgoing_channel();
+
+        incoming
+            .send(BuilderIncomingMessages::RequestBuild(target.clone()))
+            .expect("Failed to request build");
+
+        let result = timeout(Duration::from_secs(100), async {
+            let mut cancelled = None;
+            loop {
+                let msg = build_messages.recv().await?;
+                match msg {
+                    BuildOutputMessages::StartedBuild(id) if cancelled.is_none() => {
+                        while !started_marker.exists() {
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                        std::fs::remove_file(&slow)?;
+                        incoming.send(BuilderIncomingMessages::RequestBuild(target.clone()))?;
+                        eprintln!("Requested a build while build {id} is running");
+                    }
+                    BuildOutputMessages::CancelledBuild(id) => cancelled = Some(id),
+                    BuildOutputMessages::EndedBuild { id, .. } => {
+                        return anyhow::Ok((cancelled, id));
+                    }
+                    BuildOutputMessages::FailedBuild(e) => bail!("Failed Build - {e}"),
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        let (cancelled, ended) = match result {
+            Err(e) => panic!("Timed out - {e:?}"),
+            Ok(Err(e)) => panic!("Failed to Build - {e:?}"),
+            Ok(Ok(ids)) => ids,
+        };
+        let cancelled = cancelled.expect("The running build wasn't cancelled");
+        assert!(ended > cancelled);
+
+        // Long enough for the first build script to finish, had it been left running
+        tokio::time::sleep(Duration::from_secs(9)).await;
+        assert!(!finished_marker.exists());
+    }
 }
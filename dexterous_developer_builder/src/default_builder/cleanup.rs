@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashSet};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::{profile_directory, Target};
+use tracing::{debug, warn};
+
+/// The directory the default builder places a target's libraries in
+pub fn output_directory(target: &Target, profile: &str) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!(
+        "./target/hot-reload/{target}/{target}/{}",
+        profile_directory(profile)
+    ))
+}
+
+/// Reads the build id from a numbered hot reload output, such as `libgame.12.so` or `game.12.pdb`
+fn build_id(file_name: &str) -> Option<u32> {
+    numbered_output(file_name).map(|(_, id)| id)
+}
+
+/// Splits a numbered hot reload output into the library it belongs to and its build id
+fn numbered_output(file_name: &str) -> Option<(&str, u32)> {
+    let mut segments = file_name.split('.');
+    let stem = segments.next().filter(|stem| !stem.is_empty())?;
+    let id = segments.next()?;
+    segments.next()?;
+    if !id.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((stem, id.parse().ok()?))
+}
+
+/// Finds the numbered outputs of previous builds in a directory, grouped by build id
+pub fn find_numbered_outputs(
+    directory: &Utf8Path,
+) -> std::io::Result<BTreeMap<u32, Vec<Utf8PathBuf>>> {
+    let mut outputs = BTreeMap::<u32, Vec<Utf8PathBuf>>::new();
+    if !directory.exists() {
+        return Ok(outputs);
+    }
+
+    for entry in directory.read_dir_utf8()? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(id) = build_id(entry.file_name()) {
+            outputs
+                .entry(id)
+                .or_default()
+                .push(entry.path().to_path_buf());
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Removes the outputs of all but the newest `keep` builds of each library, skipping any build in `loaded`.
+///
+/// Libraries are told apart by their file stem, so a hot crate that hasn't changed in a while keeps its
+/// newest version even when other libraries have been rebuilt many times since.
+///
+/// Returns the removed files.
+pub fn remove_old_builds(
+    directory: &Utf8Path,
+    keep: usize,
+    loaded: &HashSet<u32>,
+) -> std::io::Result<Vec<Utf8PathBuf>> {
+    let mut outputs = BTreeMap::<String, BTreeMap<u32, Vec<Utf8PathBuf>>>::new();
+    for (id, files) in find_numbered_outputs(directory)? {
+        for file in files {
+            let Some((stem, _)) = file.file_name().and_then(numbered_output) else {
+                continue;
+            };
+            outputs
+                .entry(stem.to_string())
+                .or_default()
+                .entry(id)
+                .or_default()
+                .push(file);
+        }
+    }
+
+    let mut removed = vec![];
+    for builds in outputs.into_values() {
+        let stale = builds.len().saturating_sub(keep);
+        for (id, files) in builds.into_iter().take(stale) {
+            if loaded.contains(&id) {
+                debug!("Keeping build {id} - a runner still has it loaded");
+                continue;
+            }
+            for file in files {
+                match std::fs::remove_file(&file) {
+                    Ok(()) => removed.push(file),
+                    Err(e) => warn!("Couldn't remove {file} - {e}"),
+                }
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use camino::Utf8PathBuf;
+    use test_temp_dir::*;
+
+    use super::{build_id, find_numbered_outputs, remove_old_builds};
+
+    #[test]
+    fn reads_build_ids_from_numbered_outputs() {
+        assert_eq!(build_id("libgame.12.so"), Some(12));
+        assert_eq!(build_id("game.3.dll.lib"), Some(3));
+        assert_eq!(build_id("game.3.d"), Some(3));
+        assert_eq!(build_id("libgame.so"), None);
+        assert_eq!(build_id("libgame-8f3c.so"), None);
+        assert_eq!(build_id("game.12"), None);
+        assert_eq!(build_id(".12.so"), None);
+    }
+
+    #[test]
+    fn removes_all_but_the_newest_and_loaded_builds() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for file in [
+            "libgame.1.so",
+            "libgame.2.so",
+            "game.2.d",
+            "libgame.3.so",
+            "libgame.4.so",
+            "libgame.5.so",
+            "libgame.so",
+        ] {
+            std::fs::write(dir.join(file), file).unwrap();
+        }
+
+        let removed = remove_old_builds(&dir, 2, &HashSet::from([2])).unwrap();
+        assert_eq!(removed.len(), 2);
+
+        let remaining = find_numbered_outputs(&dir).unwrap();
+        assert_eq!(remaining.keys().copied().collect::<Vec<_>>(), vec![2, 4, 5]);
+        assert_eq!(remaining.get(&2).map(|files| files.len()), Some(2));
+        assert!(dir.join("libgame.so").exists());
+    }
+
+    #[test]
+    fn keeps_the_newest_versions_of_each_library() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for file in [
+            "libgameplay-8f3c.2.so",
+            "libphysics-0a1b.3.so",
+            "libphysics-0a1b.6.so",
+            "libphysics-0a1b.9.so",
+        ] {
+            std::fs::write(dir.join(file), file).unwrap();
+        }
+
+        let removed = remove_old_builds(&dir, 2, &HashSet::new()).unwrap();
+        assert_eq!(removed, vec![dir.join("libphysics-0a1b.3.so")]);
+        assert!(dir.join("libgameplay-8f3c.2.so").exists());
+    }
+}
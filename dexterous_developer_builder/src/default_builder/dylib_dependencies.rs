@@ -0,0 +1,460 @@
+use std::collections::{HashMap, HashSet};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use tracing::{debug, trace, warn};
+
+/// The dynamic libraries a build needs at runtime, along with the libraries each of them loads
+#[derive(Debug, Default, Clone)]
+pub struct DependencyGraph {
+    /// Every library in the graph, by the name it is loaded with
+    pub libraries: HashMap<String, Utf8PathBuf>,
+    /// The libraries each library in the graph loads, limited to the ones in the graph
+    pub dependencies: HashMap<String, Vec<String>>,
+}
+
+/// The imports and search paths read from a dynamic library
+#[derive(Debug, Default)]
+struct LinkedLibrary {
+    needed: Vec<String>,
+    /// Directories searched before the usual search path - `RPATH` on linux, `LC_RPATH` on macos
+    search_first: Vec<Utf8PathBuf>,
+    /// Directories searched after the usual search path - `RUNPATH` on linux
+    search_last: Vec<Utf8PathBuf>,
+}
+
+impl DependencyGraph {
+    /// Walks the libraries a root library needs, and the ones they need in turn.
+    ///
+    /// Libraries are looked up in their own `RPATH`/`RUNPATH` directories and in `searchable_files`.
+    /// Libraries that are part of every system, such as libc, are left out, as are any that can't be found.
+    pub fn resolve(
+        root_name: &str,
+        root_path: &Utf8Path,
+        searchable_files: &HashMap<String, Utf8PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let mut graph = Self::default();
+        graph
+            .libraries
+            .insert(root_name.to_string(), root_path.to_owned());
+
+        let mut pending = vec![(root_name.to_string(), root_path.to_owned())];
+        let mut visited = HashSet::new();
+
+        while let Some((name, path)) = pending.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+
+            let linked = match read_linked_library(&path) {
+                Ok(linked) => linked,
+                Err(e) if name == root_name => return Err(e),
+                Err(e) => {
+                    warn!("Couldn't read the dependencies of {name} at {path} - {e}");
+                    continue;
+                }
+            };
+            trace!("{name} needs {:?}", linked.needed);
+
+            let mut dependencies = vec![];
+            for needed in linked.needed.iter() {
+                let dependency_name = library_file_name(needed);
+                if dependency_name.is_empty() || is_system_library(needed) {
+                    continue;
+                }
+                let resolved = graph
+                    .libraries
+                    .get(dependency_name)
+                    .cloned()
+                    .or_else(|| find_library(needed, &path, &linked, searchable_files));
+                let Some(dependency_path) = resolved else {
+                    debug!("Couldn't find library {needed}, needed by {name}");
+                    continue;
+                };
+
+                dependencies.push(dependency_name.to_string());
+                graph
+                    .libraries
+                    .entry(dependency_name.to_string())
+                    .or_insert_with(|| dependency_path.clone());
+                pending.push((dependency_name.to_string(), dependency_path));
+            }
+            graph.dependencies.insert(name, dependencies);
+        }
+
+        Ok(graph)
+    }
+}
+
+fn read_linked_library(path: &Utf8Path) -> anyhow::Result<LinkedLibrary> {
+    let file = std::fs::read(path)?;
+    let object = goblin::Object::parse(&file)?;
+    let origin = path.parent().unwrap_or(Utf8Path::new("."));
+
+    let split_paths = |paths: &[&str]| -> Vec<Utf8PathBuf> {
+        paths
+            .iter()
+            .flat_map(|paths| paths.split(':'))
+            .filter(|path| !path.is_empty())
+            .map(|path| expand_origin(path, origin))
+            .collect()
+    };
+
+    Ok(match object {
+        goblin::Object::Elf(elf) => {
+            // RPATH is ignored when RUNPATH is present
+            let search_first = if elf.runpaths.is_empty() {
+                split_paths(&elf.rpaths)
+            } else {
+                vec![]
+            };
+            LinkedLibrary {
+                needed: elf.libraries.iter().map(|v| v.to_string()).collect(),
+                search_first,
+                search_last: split_paths(&elf.runpaths),
+            }
+        }
+        goblin::Object::PE(pe) => LinkedLibrary {
+            needed: pe.libraries.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        },
+        goblin::Object::Mach(goblin::mach::Mach::Binary(macho)) => LinkedLibrary {
+            needed: macho
+                .libs
+                .iter()
+                .filter(|lib| **lib != "self")
+                .map(|lib| lib.to_string())
+                .collect(),
+            search_first: split_paths(&macho.rpaths),
+            search_last: vec![],
+        },
+        goblin::Object::Mach(goblin::mach::Mach::Fat(fat)) => {
+            let mut linked = LinkedLibrary::default();
+            for arch in fat.into_iter() {
+                let Ok(goblin::mach::SingleArch::MachO(macho)) = arch else {
+                    continue;
+                };
+                for lib in macho.libs.iter().filter(|lib| **lib != "self") {
+                    if !linked.needed.iter().any(|needed| needed == lib) {
+                        linked.needed.push(lib.to_string());
+                    }
+                }
+                linked.search_first.extend(split_paths(&macho.rpaths));
+            }
+            linked
+        }
+        _ => LinkedLibrary::default(),
+    })
+}
+
+/// Replaces the loader's variables for the directory a library is in
+fn expand_origin(path: &str, origin: &Utf8Path) -> Utf8PathBuf {
+    let origin = origin.as_str();
+    Utf8PathBuf::from(
+        path.replace("${ORIGIN}", origin)
+            .replace("$ORIGIN", origin)
+            .replace("@loader_path", origin)
+            .replace("@executable_path", origin),
+    )
+}
+
+/// The name a library is loaded with, without any directory or `@rpath` prefix
+fn library_file_name(needed: &str) -> &str {
+    needed.rsplit(['/', '\\']).next().unwrap_or(needed)
+}
+
+fn find_library(
+    needed: &str,
+    dependent: &Utf8Path,
+    linked: &LinkedLibrary,
+    searchable_files: &HashMap<String, Utf8PathBuf>,
+) -> Option<Utf8PathBuf> {
+    let name = library_file_name(needed);
+
+    if let Some(relative) = needed.strip_prefix("@rpath/") {
+        return linked
+            .search_first
+            .iter()
+            .map(|dir| dir.join(relative))
+            .find(|path| path.is_file())
+            .or_else(|| searchable_files.get(name).cloned());
+    }
+    if needed.contains('/') {
+        let origin = dependent.parent().unwrap_or(Utf8Path::new("."));
+        let path = expand_origin(needed, origin);
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+
+    linked
+        .search_first
+        .iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+        .or_else(|| find_searchable_file(name, searchable_files))
+        .or_else(|| {
+            linked
+                .search_last
+                .iter()
+                .map(|dir| dir.join(name))
+                .find(|path| path.is_file())
+        })
+}
+
+/// Finds a library by name, allowing for case differences on windows and for versioned sonames,
+/// such as a `libfoo.so.1` that is only present as `libfoo.so.1.2.3`
+fn find_searchable_file(
+    name: &str,
+    searchable_files: &HashMap<String, Utf8PathBuf>,
+) -> Option<Utf8PathBuf> {
+    if let Some(path) = searchable_files.get(name) {
+        return Some(path.clone());
+    }
+
+    let lowercase = name.to_lowercase();
+    let versioned = format!("{name}.");
+    searchable_files
+        .iter()
+        .filter(|(file, _)| {
+            (lowercase.ends_with(".dll") && file.to_lowercase() == lowercase)
+                || (name.contains(".so") && file.starts_with(&versioned))
+        })
+        .min_by_key(|(file, _)| *file)
+        .map(|(_, path)| path.clone())
+}
+
+/// Libraries that are present on every system of their platform, so they never need to be sent to a runner
+fn is_system_library(needed: &str) -> bool {
+    const ELF_SYSTEM_LIBRARIES: &[&str] = &[
+        "libc",
+        "libm",
+        "libdl",
+        "libpthread",
+        "librt",
+        "libutil",
+        "libgcc_s",
+        "libresolv",
+    ];
+    const WINDOWS_SYSTEM_LIBRARIES: &[&str] = &[
+        "kernel32",
+        "kernelbase",
+        "ntdll",
+        "user32",
+        "gdi32",
+        "advapi32",
+        "shell32",
+        "ole32",
+        "oleaut32",
+        "ws2_32",
+        "bcrypt",
+        "bcryptprimitives",
+        "userenv",
+        "msvcrt",
+        "ucrtbase",
+        "secur32",
+        "crypt32",
+        "dbghelp",
+        "winmm",
+        "imm32",
+        "version",
+        "setupapi",
+        "comctl32",
+        "comdlg32",
+        "dwmapi",
+        "uxtheme",
+        "opengl32",
+        "dxgi",
+        "d3d11",
+        "d3d12",
+        "synchronization",
+    ];
+
+    if needed.starts_with("/usr/lib/") || needed.starts_with("/System/Library/") {
+        return true;
+    }
+
+    let name = library_file_name(needed);
+    if name.starts_with("ld-linux") || name.starts_with("linux-vdso") {
+        return true;
+    }
+    if let Some((stem, _)) = name.split_once(".so") {
+        return ELF_SYSTEM_LIBRARIES.contains(&stem);
+    }
+
+    let lowercase = name.to_lowercase();
+    if let Some(stem) = lowercase.strip_suffix(".dll") {
+        return stem.starts_with("api-ms-win-")
+            || stem.starts_with("ext-ms-")
+            || WINDOWS_SYSTEM_LIBRARIES.contains(&stem);
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use camino::{Utf8Path, Utf8PathBuf};
+
+    use super::{
+        expand_origin, find_searchable_file, is_system_library, library_file_name, DependencyGraph,
+    };
+    #[cfg(target_os = "linux")]
+    use test_temp_dir::*;
+
+    /// Compiles a C compatible dynamic library, such as `libleaf.so`, into a directory
+    #[cfg(target_os = "linux")]
+    fn compile_library(directory: &Utf8Path, name: &str, source: &str, link_args: &[String]) {
+        let source_file = directory.join(format!("{name}.rs"));
+        std::fs::write(&source_file, source).unwrap();
+        let mut rustc = std::process::Command::new("rustc");
+        rustc
+            .arg("--crate-type=cdylib")
+            .arg(format!("--crate-name={name}"))
+            .arg("--out-dir")
+            .arg(directory)
+            .arg(&source_file);
+        for arg in link_args {
+            rustc.arg("-C").arg(format!("link-arg={arg}"));
+        }
+        let output = rustc.output().expect("Couldn't run rustc");
+        assert!(
+            output.status.success(),
+            "Couldn't compile {name} - {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    fn skips_libraries_every_system_has() {
+        assert!(is_system_library("libc.so.6"));
+        assert!(is_system_library("ld-linux-x86-64.so.2"));
+        assert!(is_system_library("KERNEL32.dll"));
+        assert!(is_system_library("api-ms-win-core-synch-l1-2-0.dll"));
+        assert!(is_system_library("/usr/lib/libSystem.B.dylib"));
+        assert!(!is_system_library("libstd-8f3c.so"));
+        assert!(!is_system_library("libasound.so.2"));
+        assert!(!is_system_library("@rpath/libgame.dylib"));
+    }
+
+    #[test]
+    fn expands_origin_and_strips_library_prefixes() {
+        let origin = Utf8Path::new("/game/libs");
+        assert_eq!(
+            expand_origin("$ORIGIN/../deps", origin),
+            Utf8PathBuf::from("/game/libs/../deps")
+        );
+        assert_eq!(
+            expand_origin("${ORIGIN}", origin),
+            Utf8PathBuf::from("/game/libs")
+        );
+        assert_eq!(
+            expand_origin("@loader_path/Frameworks", origin),
+            Utf8PathBuf::from("/game/libs/Frameworks")
+        );
+        assert_eq!(library_file_name("@rpath/libgame.dylib"), "libgame.dylib");
+        assert_eq!(library_file_name("libgame.so"), "libgame.so");
+    }
+
+    #[test]
+    fn finds_versioned_sonames() {
+        let files = HashMap::from([
+            (
+                "libfoo.so.1.2.3".to_string(),
+                Utf8PathBuf::from("/lib/libfoo.so.1.2.3"),
+            ),
+            ("Bar.dll".to_string(), Utf8PathBuf::from("/bin/Bar.dll")),
+        ]);
+
+        assert_eq!(
+            find_searchable_file("libfoo.so.1", &files),
+            Some(Utf8PathBuf::from("/lib/libfoo.so.1.2.3"))
+        );
+        assert_eq!(
+            find_searchable_file("bar.dll", &files),
+            Some(Utf8PathBuf::from("/bin/Bar.dll"))
+        );
+        assert_eq!(find_searchable_file("libbar.so", &files), None);
+    }
+
+    #[test]
+    fn resolving_an_executable_leaves_out_system_libraries() {
+        let executable = Utf8PathBuf::from_path_buf(std::env::current_exe().unwrap()).unwrap();
+
+        let graph = DependencyGraph::resolve("test", &executable, &HashMap::new())
+            .expect("Couldn't resolve dependencies");
+
+        assert_eq!(graph.libraries.get("test"), Some(&executable));
+        assert!(graph.libraries.keys().all(|name| !is_system_library(name)));
+        assert!(graph.dependencies.contains_key("test"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resolves_transitive_libraries_through_runpaths_and_cycles() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        let leaves = dir.join("leaves");
+        std::fs::create_dir_all(&leaves).unwrap();
+
+        let leaf = r#"#[no_mangle] pub extern "C" fn leaf() -> u32 { 1 }"#;
+        compile_library(&leaves, "leaf", leaf, &[]);
+        compile_library(
+            &dir,
+            "middle",
+            r#"
+            #[link(name = "leaf")]
+            extern "C" { fn leaf() -> u32; }
+            #[no_mangle] pub extern "C" fn middle() -> u32 { 1 + unsafe { leaf() } }
+            "#,
+            &[
+                format!("-L{leaves}"),
+                "-Wl,-rpath,$ORIGIN/leaves".to_string(),
+            ],
+        );
+        // Relinking the leaf against the middle library makes the two depend on each other
+        compile_library(
+            &leaves,
+            "leaf",
+            leaf,
+            &[
+                "-Wl,--no-as-needed".to_string(),
+                format!("-L{dir}"),
+                "-lmiddle".to_string(),
+            ],
+        );
+        compile_library(
+            &dir,
+            "root",
+            r#"
+            #[link(name = "middle")]
+            extern "C" { fn middle() -> u32; }
+            #[no_mangle] pub extern "C" fn root() -> u32 { 1 + unsafe { middle() } }
+            "#,
+            &[format!("-L{dir}"), format!("-Wl,-rpath-link,{leaves}")],
+        );
+
+        // The leaf isn't searchable, so it can only be found through the middle library's runpath
+        let searchable = HashMap::from([("libmiddle.so".to_string(), dir.join("libmiddle.so"))]);
+        let graph = DependencyGraph::resolve("libroot.so", &dir.join("libroot.so"), &searchable)
+            .expect("Couldn't resolve dependencies");
+
+        assert_eq!(
+            graph.dependencies.get("libroot.so"),
+            Some(&vec!["libmiddle.so".to_string()])
+        );
+        assert_eq!(
+            graph.dependencies.get("libmiddle.so"),
+            Some(&vec!["libleaf.so".to_string()])
+        );
+        assert_eq!(
+            graph.dependencies.get("libleaf.so"),
+            Some(&vec!["libmiddle.so".to_string()])
+        );
+        assert_eq!(
+            graph.libraries.get("libleaf.so"),
+            Some(&leaves.join("libleaf.so"))
+        );
+        assert_eq!(graph.libraries.len(), 3, "{:?}", graph.libraries);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::Target;
+use tracing::warn;
+
+/// The environment variable the builder uses to pass the hot crates to the rustc wrapper, as a JSON list
+pub const HOT_CRATES_VARIABLE: &str = "DEXTEROUS_DEVELOPER_HOT_CRATES";
+
+/// The directory every version of the hot crates' dylibs is kept in, next to `deps`.
+///
+/// Builds refer to the copies in here, so they stay available after a newer version replaces them in `deps`.
+pub fn hot_crate_directory(output_directory: &Utf8Path) -> Utf8PathBuf {
+    output_directory.join("hot-crates")
+}
+
+/// The workspace crates that are compiled into their own versioned dynamic libraries.
+///
+/// A hot crate keeps the rlib and rmeta cargo expects, so cargo still treats it as fresh, and also
+/// gets a dylib named like `libgameplay-8f3c.12.so`. Crates that depend on it are pointed at the newest
+/// of those dylibs, so they link against it instead of copying it in statically.
+#[derive(Debug, Clone)]
+pub struct HotCrates {
+    crates: HashSet<String>,
+    target: Target,
+}
+
+impl HotCrates {
+    pub fn new(crates: impl IntoIterator<Item = impl AsRef<str>>, target: Target) -> Self {
+        Self {
+            crates: crates
+                .into_iter()
+                .map(|name| crate_name(name.as_ref()))
+                .collect(),
+            target,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.crates.is_empty()
+    }
+
+    pub fn contains(&self, crate_name: &str) -> bool {
+        self.crates.contains(crate_name)
+    }
+
+    /// Points an `--extern name=path` argument at the newest dylib of a hot crate,
+    /// leaving it as is for other crates or if the crate has no dylib yet
+    pub fn rewrite_extern(&self, link: &str) -> String {
+        let Some((name, path)) = link.split_once('=') else {
+            return link.to_string();
+        };
+        let path = Utf8Path::new(path);
+        let Some(stem) = path
+            .file_name()
+            .and_then(|file| file.strip_prefix("lib"))
+            .and_then(|file| {
+                file.strip_suffix(".rlib")
+                    .or_else(|| file.strip_suffix(".rmeta"))
+            })
+        else {
+            return link.to_string();
+        };
+        let Some((crate_name, _)) = stem.rsplit_once('-') else {
+            return link.to_string();
+        };
+        if !self.contains(crate_name) {
+            return link.to_string();
+        }
+        let Some(directory) = path.parent() else {
+            return link.to_string();
+        };
+
+        match self.newest_version(directory, stem) {
+            Some((_, dylib)) => format!("{name}={dylib}"),
+            None => {
+                warn!("No dynamic library for hot crate {crate_name} in {directory} - linking it statically");
+                link.to_string()
+            }
+        }
+    }
+
+    /// Finds the versions of a hot crate's dylib in a directory, by the crate's file stem, such as `gameplay-8f3c`
+    pub fn versions(&self, directory: &Utf8Path, stem: &str) -> Vec<(u32, Utf8PathBuf)> {
+        let Ok(entries) = directory.read_dir_utf8() else {
+            return vec![];
+        };
+        let prefix = format!("{}{stem}.", self.target.dynamic_lib_prefix());
+        let extension = format!(".{}", self.target.dynamic_lib_extension());
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let version = entry
+                    .file_name()
+                    .strip_prefix(&prefix)?
+                    .strip_suffix(&extension)?
+                    .parse()
+                    .ok()?;
+                Some((version, entry.path().to_path_buf()))
+            })
+            .collect()
+    }
+
+    fn newest_version(&self, directory: &Utf8Path, stem: &str) -> Option<(u32, Utf8PathBuf)> {
+        self.versions(directory, stem)
+            .into_iter()
+            .max_by_key(|(version, _)| *version)
+    }
+
+    /// Moves the rlib, rmeta and dep-info of a freshly compiled version of a hot crate to the names cargo expects,
+    /// keeps a copy of its dylib in the [`hot_crate_directory`], and takes the crate's older dylibs out of `out_dir`.
+    ///
+    /// Only one dylib of each crate can stay in the output directory - rustc refuses to pick between
+    /// two candidates for the same crate. The older versions remain in the hot crate directory until
+    /// [`remove_old_builds`](super::cleanup::remove_old_builds) prunes them, since earlier builds still refer to them.
+    pub fn finish_compilation(
+        &self,
+        out_dir: &Utf8Path,
+        crate_name: &str,
+        extra_filename: &str,
+        version: u32,
+    ) -> std::io::Result<()> {
+        let stem = format!("{crate_name}{extra_filename}");
+        for (versioned, expected) in [
+            (
+                format!("lib{stem}.{version}.rlib"),
+                format!("lib{stem}.rlib"),
+            ),
+            (
+                format!("lib{stem}.{version}.rmeta"),
+                format!("lib{stem}.rmeta"),
+            ),
+            (format!("{stem}.{version}.d"), format!("{stem}.d")),
+        ] {
+            let versioned = out_dir.join(versioned);
+            if versioned.exists() {
+                std::fs::rename(&versioned, out_dir.join(expected))?;
+            }
+        }
+
+        let Some(output_directory) = out_dir.parent() else {
+            return Ok(());
+        };
+        let library_directory = hot_crate_directory(output_directory);
+        std::fs::create_dir_all(&library_directory)?;
+
+        for (old_version, path) in self.versions(out_dir, &stem) {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            let kept = library_directory.join(file_name);
+            if old_version == version {
+                if kept.exists() {
+                    std::fs::remove_file(&kept)?;
+                }
+                if std::fs::hard_link(&path, &kept).is_err() {
+                    std::fs::copy(&path, &kept)?;
+                }
+            } else if kept.exists() {
+                std::fs::remove_file(path)?;
+            } else {
+                std::fs::rename(path, kept)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Crate names use underscores where package names can use dashes
+fn crate_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use dexterous_developer_types::Target;
+    use test_temp_dir::*;
+
+    use super::{hot_crate_directory, HotCrates};
+
+    #[test]
+    fn points_hot_crates_at_their_newest_dylib() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in [
+            "libgameplay-8f3c.rlib",
+            "libgameplay-8f3c.2.so",
+            "libgameplay-8f3c.10.so",
+            "libgameplay-0a1b.11.so",
+        ] {
+            std::fs::write(dir.join(file), file).unwrap();
+        }
+
+        let hot = HotCrates::new(["gameplay", "ui-widgets"], Target::Linux);
+        assert!(hot.contains("ui_widgets"));
+
+        assert_eq!(
+            hot.rewrite_extern(&format!("gameplay={dir}/libgameplay-8f3c.rlib")),
+            format!("gameplay={dir}/libgameplay-8f3c.10.so")
+        );
+        assert_eq!(
+            hot.rewrite_extern(&format!("noprelude:play={dir}/libgameplay-8f3c.rmeta")),
+            format!("noprelude:play={dir}/libgameplay-8f3c.10.so")
+        );
+
+        let untouched = [
+            format!("serde={dir}/libserde-1234.rlib"),
+            format!("ui_widgets={dir}/libui_widgets-5678.rlib"),
+            "gameplay".to_string(),
+        ];
+        for link in untouched {
+            assert_eq!(hot.rewrite_extern(&link), link);
+        }
+    }
+
+    #[test]
+    fn leaves_cargo_outputs_in_place_and_one_dylib_per_crate() {
+        let output = test_temp_dir!();
+        let output = Utf8PathBuf::from_path_buf(output.as_path_untracked().to_path_buf()).unwrap();
+        let dir = output.join("deps");
+        let kept = hot_crate_directory(&output);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&kept).unwrap();
+        std::fs::write(kept.join("libgameplay-8f3c.3.so"), "libgameplay-8f3c.3.so").unwrap();
+        for file in [
+            "libgameplay-8f3c.3.so",
+            "libgameplay-8f3c.4.so",
+            "libgameplay-8f3c.4.rlib",
+            "libgameplay-8f3c.4.rmeta",
+            "gameplay-8f3c.4.d",
+            "libgameplay-0a1b.2.so",
+        ] {
+            std::fs::write(dir.join(file), file).unwrap();
+        }
+
+        let hot = HotCrates::new(["gameplay"], Target::Linux);
+        hot.finish_compilation(&dir, "gameplay", "-8f3c", 4)
+            .unwrap();
+
+        let mut files = dir
+            .read_dir_utf8()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "gameplay-8f3c.d",
+                "libgameplay-0a1b.2.so",
+                "libgameplay-8f3c.4.so",
+                "libgameplay-8f3c.rlib",
+                "libgameplay-8f3c.rmeta",
+            ]
+        );
+
+        let mut kept = kept
+            .read_dir_utf8()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string())
+            .collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, vec!["libgameplay-8f3c.3.so", "libgameplay-8f3c.4.so"]);
+    }
+}
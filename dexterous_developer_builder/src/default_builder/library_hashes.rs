@@ -0,0 +1,169 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::types::{hash_file, CompletedBuild, HashedFileRecord};
+
+/// The libraries sent with the previous build, so the next one only needs to hash and send the ones that changed
+#[derive(Debug, Default, Clone)]
+pub struct LibraryHashes {
+    known: HashMap<String, KnownLibrary>,
+}
+
+#[derive(Debug, Clone)]
+struct KnownLibrary {
+    local_path: Utf8PathBuf,
+    hash: [u8; 32],
+    /// The size and modification time the hash was taken at - unknown for libraries restored from a previous session
+    fingerprint: Option<(u64, SystemTime)>,
+}
+
+/// A build's libraries, split by whether a runner that has the previous build needs them
+#[derive(Debug, Default)]
+pub struct LibraryChanges {
+    /// Every library in the build
+    pub libraries: Vec<HashedFileRecord>,
+    /// The names of the libraries that are identical to the ones in the previous build
+    pub unchanged: HashSet<String>,
+}
+
+impl LibraryChanges {
+    /// The libraries a runner with the previous build still needs to download
+    pub fn changed(&self) -> Vec<HashedFileRecord> {
+        self.libraries
+            .iter()
+            .filter(|library| !self.unchanged.contains(&library.name))
+            .cloned()
+            .collect()
+    }
+}
+
+impl LibraryHashes {
+    /// Starts from a build restored from a previous session, which the server serves until a new build completes
+    pub fn from_completed_build(build: &CompletedBuild) -> Self {
+        let known = build
+            .libraries
+            .iter()
+            .map(|library| {
+                (
+                    library.name.clone(),
+                    KnownLibrary {
+                        local_path: library.local_path.clone(),
+                        hash: library.hash,
+                        fingerprint: None,
+                    },
+                )
+            })
+            .collect();
+        Self { known }
+    }
+
+    /// Hashes a build's libraries and compares them with the previous build's.
+    ///
+    /// Libraries whose path, size and modification time match the previous build reuse its hash rather than being read again.
+    /// The root library always counts as changed, since loading it is what applies a build.
+    pub fn update(
+        &mut self,
+        root_library: &str,
+        libraries: &HashMap<String, Utf8PathBuf>,
+        dependencies: &HashMap<String, Vec<String>>,
+    ) -> anyhow::Result<LibraryChanges> {
+        let mut changes = LibraryChanges::default();
+        let mut known = HashMap::with_capacity(libraries.len());
+
+        for (name, local_path) in libraries.iter() {
+            let fingerprint = fingerprint(local_path);
+            let previous = self
+                .known
+                .get(name)
+                .filter(|previous| previous.local_path == *local_path);
+
+            let hash = match previous {
+                Some(previous) if fingerprint.is_some() && previous.fingerprint == fingerprint => {
+                    previous.hash
+                }
+                _ => hash_file(local_path)?,
+            };
+            if name != root_library && previous.is_some_and(|previous| previous.hash == hash) {
+                changes.unchanged.insert(name.clone());
+            }
+
+            changes.libraries.push(HashedFileRecord {
+                name: name.clone(),
+                local_path: local_path.clone(),
+                relative_path: Utf8PathBuf::from(format!("./{name}")),
+                hash,
+                dependencies: dependencies.get(name).cloned().unwrap_or_default(),
+            });
+            known.insert(
+                name.clone(),
+                KnownLibrary {
+                    local_path: local_path.clone(),
+                    hash,
+                    fingerprint,
+                },
+            );
+        }
+
+        self.known = known;
+        Ok(changes)
+    }
+}
+
+fn fingerprint(path: &Utf8Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use camino::Utf8PathBuf;
+    use test_temp_dir::*;
+
+    use super::LibraryHashes;
+
+    #[test]
+    fn only_changed_libraries_and_the_root_are_sent_again() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        let root = dir.join("libgame.so");
+        let std_lib = dir.join("libstd.so");
+        let audio = dir.join("libaudio.so");
+        std::fs::write(&root, "game").unwrap();
+        std::fs::write(&std_lib, "std").unwrap();
+        std::fs::write(&audio, "audio").unwrap();
+
+        let libraries = HashMap::from([
+            ("libgame.so".to_string(), root.clone()),
+            ("libstd.so".to_string(), std_lib.clone()),
+            ("libaudio.so".to_string(), audio.clone()),
+        ]);
+        let mut hashes = LibraryHashes::default();
+
+        let first = hashes
+            .update("libgame.so", &libraries, &HashMap::new())
+            .unwrap();
+        assert_eq!(first.libraries.len(), 3);
+        assert!(first.unchanged.is_empty());
+
+        std::fs::write(&audio, "new audio").unwrap();
+        let second = hashes
+            .update("libgame.so", &libraries, &HashMap::new())
+            .unwrap();
+        let mut changed = second
+            .changed()
+            .into_iter()
+            .map(|library| library.name)
+            .collect::<Vec<_>>();
+        changed.sort();
+        assert_eq!(changed, vec!["libaudio.so", "libgame.so"]);
+        assert_eq!(second.libraries.len(), 3);
+        assert!(second.unchanged.contains("libstd.so"));
+    }
+}
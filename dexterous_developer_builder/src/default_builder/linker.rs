@@ -0,0 +1,110 @@
+use dexterous_developer_types::{Linker, Target, TargetBuildSettings};
+use tracing::{info, warn};
+
+/// Checks whether a linker can be found on this machine
+pub fn linker_available(linker: &Linker) -> bool {
+    match linker {
+        Linker::Lld => ["ld.lld", "lld", "lld-link"]
+            .iter()
+            .any(|name| which::which(name).is_ok()),
+        Linker::Mold => ["mold", "ld.mold"]
+            .iter()
+            .any(|name| which::which(name).is_ok()),
+        Linker::System => true,
+        Linker::Custom(path) => path.exists() || which::which(path).is_ok(),
+    }
+}
+
+/// Picks the linker to use for a target - the configured one if it is available,
+/// otherwise lld, and otherwise the system linker
+pub fn select_linker(target: &Target, settings: &TargetBuildSettings) -> Linker {
+    let configured = settings.linker_for(target);
+
+    let Some(linker) = [configured.clone(), Linker::Lld, Linker::System]
+        .into_iter()
+        .find(linker_available)
+    else {
+        return Linker::System;
+    };
+
+    if linker == configured {
+        info!("Linking {target} with {linker}");
+    } else {
+        warn!("Couldn't find {configured} - linking {target} with {linker} instead");
+    }
+    linker
+}
+
+/// The rustc flag that selects a linker, if it needs one
+pub fn linker_flag(linker: &Linker) -> Option<String> {
+    match linker {
+        Linker::Lld => Some("-Clink-arg=-fuse-ld=lld".to_string()),
+        Linker::Mold => Some("-Clink-arg=-fuse-ld=mold".to_string()),
+        Linker::System => None,
+        Linker::Custom(path) => Some(format!("-Clinker={path}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use dexterous_developer_types::{Linker, Target, TargetBuildSettings};
+    use test_temp_dir::test_temp_dir;
+
+    use super::{linker_available, linker_flag, select_linker};
+
+    #[test]
+    fn uses_the_configured_linker_when_it_exists() {
+        let dir = test_temp_dir!();
+        let path =
+            Utf8PathBuf::from_path_buf(dir.as_path_untracked().join("custom-linker")).unwrap();
+        std::fs::create_dir_all(dir.as_path_untracked()).unwrap();
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+
+        let settings = TargetBuildSettings {
+            linker: Some(Linker::Custom(path.clone())),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_linker(&Target::Linux, &settings),
+            Linker::Custom(path)
+        );
+
+        let settings = TargetBuildSettings {
+            linker: Some(Linker::System),
+            ..Default::default()
+        };
+        assert_eq!(select_linker(&Target::Linux, &settings), Linker::System);
+    }
+
+    #[test]
+    fn falls_back_when_the_configured_linker_is_missing() {
+        let dir = test_temp_dir!();
+        let settings = TargetBuildSettings {
+            linker: Some(Linker::Custom(
+                Utf8PathBuf::from_path_buf(dir.as_path_untracked().join("missing-linker")).unwrap(),
+            )),
+            ..Default::default()
+        };
+
+        let fallback = if linker_available(&Linker::Lld) {
+            Linker::Lld
+        } else {
+            Linker::System
+        };
+        assert_eq!(select_linker(&Target::Linux, &settings), fallback);
+    }
+
+    #[test]
+    fn only_the_system_linker_needs_no_flags() {
+        assert_eq!(linker_flag(&Linker::System), None);
+        assert_eq!(
+            linker_flag(&Linker::Mold).as_deref(),
+            Some("-Clink-arg=-fuse-ld=mold")
+        );
+        assert_eq!(
+            linker_flag(&Linker::Custom(Utf8PathBuf::from("/bin/cc"))).as_deref(),
+            Some("-Clinker=/bin/cc")
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use camino::Utf8PathBuf;
+use cargo_metadata::Metadata;
+
+use crate::types::hash_file;
+
+/// The parts of `cargo metadata` a build needs, along with the files that would change them
+#[derive(Debug, Clone)]
+pub struct CachedMetadata {
+    pub artifact_name: String,
+    /// The manifest of the package containing the selected example, if an example was selected
+    pub manifest_path: Option<Utf8PathBuf>,
+    /// The folders to watch for code changes when none are configured - see `derive_code_watch_folders`
+    pub code_watch_folders: Vec<Utf8PathBuf>,
+    inputs: Vec<(Utf8PathBuf, Option<[u8; 32]>)>,
+}
+
+impl CachedMetadata {
+    pub fn new(
+        metadata: &Metadata,
+        artifact_name: String,
+        manifest_path: Option<Utf8PathBuf>,
+        code_watch_folders: Vec<Utf8PathBuf>,
+    ) -> Self {
+        let inputs = metadata_inputs(metadata)
+            .into_iter()
+            .map(|path| {
+                let hash = hash_file(&path).ok();
+                (path, hash)
+            })
+            .collect();
+
+        Self {
+            artifact_name,
+            manifest_path,
+            code_watch_folders,
+            inputs,
+        }
+    }
+
+    /// Checks that none of the manifests, the lock file or the cargo config have changed since the metadata was read
+    pub fn is_current(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|(path, hash)| hash_file(path).ok() == *hash)
+    }
+}
+
+/// The files in a workspace that can change the output of `cargo metadata`
+fn metadata_inputs(metadata: &Metadata) -> Vec<Utf8PathBuf> {
+    let root = &metadata.workspace_root;
+    let mut inputs = vec![
+        root.join("Cargo.toml"),
+        root.join("Cargo.lock"),
+        root.join(".cargo").join("config.toml"),
+        root.join(".cargo").join("config"),
+    ];
+    for package in metadata.packages.iter() {
+        if package.manifest_path.starts_with(root) && !inputs.contains(&package.manifest_path) {
+            inputs.push(package.manifest_path.clone());
+        }
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use cargo_metadata::MetadataCommand;
+    use test_temp_dir::*;
+
+    use super::{metadata_inputs, CachedMetadata};
+    use crate::types::hash_file;
+
+    #[test]
+    fn watches_the_workspace_manifests_lock_file_and_config() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        for (path, content) in [
+            (
+                "workspace/Cargo.toml",
+                "[workspace]\nmembers = [\"game\"]\nresolver = \"2\"\n",
+            ),
+            (
+                "workspace/game/Cargo.toml",
+                "[package]\nname = \"game\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nshared = { path = \"../../shared\" }\n",
+            ),
+            ("workspace/game/src/lib.rs", ""),
+            ("workspace/.cargo/config.toml", "[build]\n"),
+            (
+                "shared/Cargo.toml",
+                "[package]\nname = \"shared\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+            ),
+            ("shared/src/lib.rs", ""),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let metadata = MetadataCommand::new()
+            .manifest_path(dir.join("workspace").join("Cargo.toml"))
+            .exec()
+            .expect("Couldn't get cargo metadata");
+        let root = &metadata.workspace_root;
+
+        let inputs = metadata_inputs(&metadata);
+
+        assert!(inputs.contains(&root.join("Cargo.toml")));
+        assert!(inputs.contains(&root.join("Cargo.lock")));
+        assert!(inputs.contains(&root.join(".cargo").join("config.toml")));
+        assert!(inputs.contains(&root.join("game").join("Cargo.toml")));
+        assert_eq!(inputs.len(), 5, "{inputs:?}");
+    }
+
+    #[test]
+    fn is_invalidated_when_an_input_changes() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        let lock = dir.join("Cargo.lock");
+        std::fs::write(&manifest, "[package]").unwrap();
+
+        let cached = CachedMetadata {
+            artifact_name: "game".to_string(),
+            manifest_path: None,
+            code_watch_folders: vec![],
+            inputs: [manifest.clone(), lock.clone()]
+                .into_iter()
+                .map(|path| {
+                    let hash = hash_file(&path).ok();
+                    (path, hash)
+                })
+                .collect(),
+        };
+        assert!(cached.is_current());
+
+        std::fs::write(&manifest, "[package]").unwrap();
+        assert!(cached.is_current());
+
+        std::fs::write(&lock, "version = 3").unwrap();
+        assert!(!cached.is_current());
+    }
+}
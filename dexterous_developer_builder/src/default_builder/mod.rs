@@ -1,2 +1,11 @@
 pub mod builder;
+pub mod cleanup;
+pub mod dylib_dependencies;
+pub mod hot_crates;
+pub mod library_hashes;
+pub mod linker;
+pub mod metadata_cache;
+pub mod persisted_state;
+pub mod process_group;
 pub mod rustc;
+pub mod toolchain;
@@ -0,0 +1,129 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::types::CompletedBuild;
+
+const STATE_FILE_NAME: &str = "hot-reload-state.json";
+
+/// The builder state that outlives a server session, so a restarted server continues
+/// numbering builds where the previous one stopped and can serve its last good build
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PersistedBuildState {
+    /// The id the next build will use
+    pub next_id: u32,
+    pub previous_versions: Vec<(String, Utf8PathBuf)>,
+    pub last_completed_build: Option<CompletedBuild>,
+}
+
+impl PersistedBuildState {
+    /// Loads the state stored in an output directory, dropping anything whose files no longer exist
+    pub fn load(directory: &Utf8Path) -> Option<Self> {
+        let path = directory.join(STATE_FILE_NAME);
+        if !path.exists() {
+            return None;
+        }
+
+        let mut state = match std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(serde_json::from_slice::<Self>(&file)?))
+        {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Couldn't read the hot reload state at {path} - {e}");
+                return None;
+            }
+        };
+
+        state.previous_versions.retain(|(_, path)| path.exists());
+        if let Some(build) = &state.last_completed_build {
+            if let Some(missing) = build
+                .libraries
+                .iter()
+                .find(|library| !library.local_path.exists())
+            {
+                debug!(
+                    "Not restoring build {} - {} is missing",
+                    build.id, missing.local_path
+                );
+                state.last_completed_build = None;
+            }
+        }
+        state.next_id = state.next_id.max(1);
+
+        Some(state)
+    }
+
+    /// Stores the state in an output directory
+    pub fn save(&self, directory: &Utf8Path) -> anyhow::Result<()> {
+        let path = directory.join(STATE_FILE_NAME);
+        let temporary = directory.join(format!("{STATE_FILE_NAME}.tmp"));
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    /// Removes the state stored in an output directory, so the next build starts from scratch
+    pub fn clear(directory: &Utf8Path) -> std::io::Result<()> {
+        let path = directory.join(STATE_FILE_NAME);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use test_temp_dir::*;
+
+    use super::PersistedBuildState;
+    use crate::types::{CompletedBuild, HashedFileRecord};
+
+    #[test]
+    fn restores_saved_state_without_missing_files() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert!(PersistedBuildState::load(&dir).is_none());
+
+        let kept = dir.join("libgame.4.so");
+        let removed = dir.join("libgame.3.so");
+        std::fs::write(&kept, "4").unwrap();
+
+        PersistedBuildState {
+            next_id: 5,
+            previous_versions: vec![
+                ("game.3".to_string(), removed.clone()),
+                ("game.4".to_string(), kept.clone()),
+            ],
+            last_completed_build: Some(CompletedBuild {
+                id: 4,
+                libraries: vec![HashedFileRecord::new(
+                    "./libgame.4.so",
+                    kept.clone(),
+                    "libgame.4.so",
+                    [0; 32],
+                )],
+                root_library: "libgame.4.so".to_string(),
+            }),
+        }
+        .save(&dir)
+        .unwrap();
+
+        let state = PersistedBuildState::load(&dir).unwrap();
+        assert_eq!(state.next_id, 5);
+        assert_eq!(
+            state.previous_versions,
+            vec![("game.4".to_string(), kept.clone())]
+        );
+        assert_eq!(state.last_completed_build.map(|build| build.id), Some(4));
+
+        std::fs::remove_file(&kept).unwrap();
+        let state = PersistedBuildState::load(&dir).unwrap();
+        assert!(state.previous_versions.is_empty());
+        assert!(state.last_completed_build.is_none());
+    }
+}
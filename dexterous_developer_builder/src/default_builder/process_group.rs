@@ -0,0 +1,125 @@
+use tokio::process::{Child, Command};
+use tracing::warn;
+
+/// Starts a command in its own process group, so [`ProcessGroup::kill`] can stop everything it starts.
+///
+/// Cargo runs rustc and the rustc wrapper as child processes - killing only cargo leaves them running,
+/// writing into the same output directory as the next build.
+pub fn isolate(command: &mut Command) {
+    #[cfg(unix)]
+    command.process_group(0);
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// The processes started by a command that was [`isolate`]d
+pub struct ProcessGroup {
+    #[cfg(unix)]
+    id: Option<i32>,
+    /// A job object holding the process - kept as an integer so the group can be held across awaits
+    #[cfg(windows)]
+    job: Option<isize>,
+}
+
+impl ProcessGroup {
+    pub fn new(child: &Child) -> Self {
+        #[cfg(unix)]
+        {
+            Self {
+                id: child.id().and_then(|id| i32::try_from(id).ok()),
+            }
+        }
+        #[cfg(windows)]
+        {
+            Self {
+                job: windows::assign_to_job(child),
+            }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = child;
+            Self {}
+        }
+    }
+
+    /// Kills every process in the group, and waits for the process that started it to exit
+    pub async fn kill(&self, child: &mut Child) {
+        #[cfg(unix)]
+        if let Some(id) = self.id {
+            // SAFETY: sending a signal has no memory safety requirements - a negative id targets the process group
+            if unsafe { libc::kill(-id, libc::SIGKILL) } != 0 {
+                warn!(
+                    "Couldn't kill process group {id} - {}",
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+        #[cfg(windows)]
+        if let Some(job) = self.job {
+            windows::terminate_job(job);
+        }
+        if let Err(e) = child.kill().await {
+            warn!("Couldn't kill process - {e}");
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(job) = self.job.take() {
+            windows::close_job(job);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use tokio::process::Child;
+    use tracing::warn;
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        System::JobObjects::{AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject},
+    };
+
+    pub fn assign_to_job(child: &Child) -> Option<isize> {
+        let process = child.raw_handle()?;
+        // SAFETY: the job is created without a name or security attributes, and the process handle is owned by `child`
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job as usize == 0 {
+                warn!(
+                    "Couldn't create a job object - {}",
+                    std::io::Error::last_os_error()
+                );
+                return None;
+            }
+            if AssignProcessToJobObject(job, process as HANDLE) == 0 {
+                warn!(
+                    "Couldn't add the build to a job object - {}",
+                    std::io::Error::last_os_error()
+                );
+                CloseHandle(job);
+                return None;
+            }
+            Some(job as isize)
+        }
+    }
+
+    pub fn terminate_job(job: isize) {
+        // SAFETY: the handle came from `assign_to_job` and is only closed when the group is dropped
+        if unsafe { TerminateJobObject(job as HANDLE, 1) } == 0 {
+            warn!(
+                "Couldn't stop the build's job object - {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    pub fn close_job(job: isize) {
+        // SAFETY: the handle came from `assign_to_job` and isn't used again
+        unsafe {
+            CloseHandle(job as HANDLE);
+        }
+    }
+}
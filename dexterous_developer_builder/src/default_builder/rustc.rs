@@ -2,20 +2,32 @@ use std::{process::Stdio, str::FromStr};
 
 use anyhow::{anyhow, bail};
 use camino::Utf8PathBuf;
+use dexterous_developer_types::Target;
+use tracing::warn;
 
-use super::builder::DefaultRunParams;
+use super::{
+    builder::DefaultRunParams,
+    hot_crates::{HotCrates, HOT_CRATES_VARIABLE},
+};
 
 pub async fn default_rustc() -> anyhow::Result<()> {
     let package_name = std::env::var("DEXTEROUS_DEVELOPER_PACKAGE_NAME")?;
     let output_file = std::env::var("DEXTEROUS_DEVELOPER_OUTPUT_FILE")?;
     let default_run_params: DefaultRunParams =
         serde_json::from_str(&std::env::var("DEXTEROUS_DEVELOPER_DEFAULT_RUN")?)?;
+    let target = Target::from_str(&std::env::var("DEXTEROUS_DEVELOPER_LINKER_TARGET")?)?;
+    let hot_crates: Vec<String> = match std::env::var(HOT_CRATES_VARIABLE) {
+        Ok(hot_crates) => serde_json::from_str(&hot_crates)?,
+        Err(_) => vec![],
+    };
+    let hot_crates = HotCrates::new(hot_crates, target);
 
     let rustc = Rustc::new(
         std::env::args(),
         &package_name,
         &output_file,
         &default_run_params,
+        &hot_crates,
     )
     .await?;
 
@@ -34,6 +46,15 @@ struct Rustc {
 #[allow(clippy::large_enum_variant)]
 enum RustcOperation {
     Passthrough(Vec<String>),
+    /// A crate listed in `hot_crates`, compiled into a versioned dylib as well as the rlib cargo expects
+    HotCrate {
+        args: Vec<String>,
+        hot_crates: HotCrates,
+        crate_name: String,
+        out_dir: Utf8PathBuf,
+        extra_filename: String,
+        version: u32,
+    },
     MainCompilation {
         crate_name: String,
         edition: u32,
@@ -59,6 +80,7 @@ impl Rustc {
         package: &str,
         output_file: &str,
         run_params: &DefaultRunParams,
+        hot_crates: &HotCrates,
     ) -> anyhow::Result<Self> {
         let _current_executable = args.next();
 
@@ -86,21 +108,31 @@ impl Rustc {
             }
         }
 
+        let version = match run_params {
+            DefaultRunParams::InitialRun => 1,
+            DefaultRunParams::Patch { id, .. } => *id,
+        };
+
         let operation = {
-            let mut is_passthrough = true;
+            let mut compiled_crate = None;
             let mut args_iter = args.iter();
             while let Some(arg) = args_iter.next() {
                 if arg.as_str() == "--crate-name" {
-                    if let Some(name) = args_iter.next() {
-                        if name.as_str() == package {
-                            is_passthrough = false;
-                            break;
-                        }
-                    }
+                    compiled_crate = args_iter.next().cloned();
+                    break;
                 }
             }
-            if is_passthrough {
-                RustcOperation::Passthrough(args)
+            let compiled_crate = compiled_crate.unwrap_or_default();
+
+            if compiled_crate != package {
+                let hot_crate = if hot_crates.contains(&compiled_crate) {
+                    hot_crate_operation(&args, &compiled_crate, version, hot_crates)?
+                } else {
+                    None
+                };
+                hot_crate.unwrap_or_else(|| {
+                    RustcOperation::Passthrough(rewrite_externs(args, hot_crates))
+                })
             } else {
                 let mut crate_name = None;
                 let mut edition = 2021;
@@ -154,8 +186,8 @@ impl Rustc {
                             library_links.push(a);
                         }
                     } else if arg == "--extern" {
-                        if let Some(a) = args_iter.next().cloned() {
-                            extern_links.push(a);
+                        if let Some(a) = args_iter.next() {
+                            extern_links.push(hot_crates.rewrite_extern(a));
                         }
                     } else if arg.ends_with(".rs") {
                         let path = Utf8PathBuf::from_str(arg)?;
@@ -179,10 +211,7 @@ impl Rustc {
                     .ok_or(anyhow!("No Parent for Output File"))?
                     .to_owned();
 
-                let file_name_extras = match run_params {
-                    DefaultRunParams::InitialRun => ".1".to_string(),
-                    DefaultRunParams::Patch { id, .. } => format!(".{id}"),
-                };
+                let file_name_extras = format!(".{version}");
 
                 RustcOperation::MainCompilation {
                     crate_name: crate_name.ok_or(anyhow!("Couldn't determine crate name"))?,
@@ -213,6 +242,7 @@ impl Rustc {
 
     async fn run(self) -> anyhow::Result<std::process::ExitStatus> {
         let mut command = WrappedCommand::new(self.executable);
+        let mut hot_crate = None;
 
         match self.operation {
             RustcOperation::MainCompilation {
@@ -284,6 +314,17 @@ impl Rustc {
             RustcOperation::Passthrough(args) => {
                 command.args(args.iter());
             }
+            RustcOperation::HotCrate {
+                args,
+                hot_crates,
+                crate_name,
+                out_dir,
+                extra_filename,
+                version,
+            } => {
+                command.args(args.iter());
+                hot_crate = Some((hot_crates, crate_name, out_dir, extra_filename, version));
+            }
         };
 
         if let Some(file) = self.arg_file {
@@ -296,8 +337,126 @@ impl Rustc {
 
         let mut child = command.spawn()?;
 
-        Ok(child.wait().await?)
+        let status = child.wait().await?;
+        if let Some((hot_crates, crate_name, out_dir, extra_filename, version)) = hot_crate {
+            if status.success() {
+                hot_crates.finish_compilation(&out_dir, &crate_name, &extra_filename, version)?;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Rewrites cargo's arguments for a hot crate, adding a dylib with the build's version in its name.
+///
+/// Returns `None` for anything other than the crate's library, such as its build script or tests.
+fn hot_crate_operation(
+    args: &[String],
+    crate_name: &str,
+    version: u32,
+    hot_crates: &HotCrates,
+) -> anyhow::Result<Option<RustcOperation>> {
+    let mut converted = Vec::with_capacity(args.len() + 4);
+    let mut is_library = false;
+    let mut out_dir = None;
+    let mut extra_filename = None;
+
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--crate-type" => {
+                let crate_type = args_iter
+                    .next()
+                    .ok_or(anyhow!("No crate type for {crate_name}"))?;
+                is_library |= crate_type == "lib" || crate_type == "rlib";
+                converted.push(arg.clone());
+                converted.push(crate_type.clone());
+            }
+            "-C" => {
+                let Some(codegen) = args_iter.next() else {
+                    converted.push(arg.clone());
+                    continue;
+                };
+                converted.push(arg.clone());
+                match codegen.strip_prefix("extra-filename=") {
+                    Some(extra) => {
+                        converted.push(format!("extra-filename={extra}.{version}"));
+                        extra_filename = Some(extra.to_string());
+                    }
+                    None => converted.push(codegen.clone()),
+                }
+            }
+            "--out-dir" => {
+                let dir = args_iter.next().ok_or(anyhow!("No Out Dir"))?;
+                out_dir = Some(Utf8PathBuf::from(dir));
+                converted.push(arg.clone());
+                converted.push(dir.clone());
+            }
+            "--extern" => {
+                converted.push(arg.clone());
+                if let Some(link) = args_iter.next() {
+                    converted.push(hot_crates.rewrite_extern(link));
+                }
+            }
+            // Cargo starts on dependent crates as soon as rustc reports the rmeta, but they need the dylib,
+            // which is only in place once rustc exits
+            json if json.starts_with("--json=") => {
+                let kinds = json
+                    .trim_start_matches("--json=")
+                    .split(',')
+                    .filter(|kind| *kind != "artifacts")
+                    .collect::<Vec<_>>();
+                converted.push(format!("--json={}", kinds.join(",")));
+            }
+            _ => converted.push(arg.clone()),
+        }
+    }
+
+    if !is_library {
+        return Ok(None);
     }
+    let Some(out_dir) = out_dir else {
+        warn!("No output directory for hot crate {crate_name} - compiling it normally");
+        return Ok(None);
+    };
+    let extra_filename = match extra_filename {
+        Some(extra) => extra,
+        None => {
+            converted.push("-C".to_string());
+            converted.push(format!("extra-filename=.{version}"));
+            String::new()
+        }
+    };
+    converted.push("--crate-type".to_string());
+    converted.push("dylib".to_string());
+
+    Ok(Some(RustcOperation::HotCrate {
+        args: converted,
+        hot_crates: hot_crates.clone(),
+        crate_name: crate_name.to_string(),
+        out_dir,
+        extra_filename,
+        version,
+    }))
+}
+
+/// Points the `--extern` arguments for hot crates at their dylibs
+fn rewrite_externs(args: Vec<String>, hot_crates: &HotCrates) -> Vec<String> {
+    if hot_crates.is_empty() {
+        return args;
+    }
+    let mut after_extern = false;
+    args.into_iter()
+        .map(|arg| {
+            let arg = if after_extern {
+                hot_crates.rewrite_extern(&arg)
+            } else {
+                arg
+            };
+            after_extern = arg == "--extern";
+            arg
+        })
+        .collect()
 }
 
 struct WrappedCommand {
@@ -350,3 +509,157 @@ impl WrappedCommand {
         Ok(cmd)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use camino::Utf8PathBuf;
+    use dexterous_developer_types::Target;
+    use test_temp_dir::*;
+
+    use super::{hot_crate_operation, rewrite_externs, HotCrates, RustcOperation};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn compiles_hot_crates_into_versioned_dylibs() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("libphysics-0a1b.3.so"), "physics").unwrap();
+
+        let hot_crates = HotCrates::new(["gameplay", "physics"], Target::Linux);
+        let physics = format!("physics={dir}/libphysics-0a1b.rmeta");
+        let cargo_args = args(&[
+            "--crate-name",
+            "gameplay",
+            "--edition=2021",
+            "gameplay/src/lib.rs",
+            "--error-format=json",
+            "--json=diagnostic-rendered-ansi,artifacts,future-incompat",
+            "--crate-type",
+            "lib",
+            "--emit=dep-info,metadata,link",
+            "-C",
+            "metadata=8f3c",
+            "-C",
+            "extra-filename=-8f3c",
+            "--out-dir",
+            dir.as_str(),
+            "--extern",
+            &physics,
+        ]);
+
+        let Some(RustcOperation::HotCrate {
+            args: converted,
+            out_dir,
+            extra_filename,
+            version,
+            ..
+        }) = hot_crate_operation(&cargo_args, "gameplay", 7, &hot_crates).unwrap()
+        else {
+            panic!("Not compiled as a hot crate");
+        };
+
+        assert_eq!(out_dir, dir);
+        assert_eq!(extra_filename, "-8f3c");
+        assert_eq!(version, 7);
+        assert_eq!(
+            converted,
+            args(&[
+                "--crate-name",
+                "gameplay",
+                "--edition=2021",
+                "gameplay/src/lib.rs",
+                "--error-format=json",
+                "--json=diagnostic-rendered-ansi,future-incompat",
+                "--crate-type",
+                "lib",
+                "--emit=dep-info,metadata,link",
+                "-C",
+                "metadata=8f3c",
+                "-C",
+                "extra-filename=-8f3c.7",
+                "--out-dir",
+                dir.as_str(),
+                "--extern",
+                &format!("physics={dir}/libphysics-0a1b.3.so"),
+                "--crate-type",
+                "dylib",
+            ])
+        );
+    }
+
+    #[test]
+    fn only_the_library_of_a_hot_crate_becomes_a_dylib() {
+        let hot_crates = HotCrates::new(["gameplay"], Target::Linux);
+
+        let build_script = args(&[
+            "--crate-name",
+            "gameplay",
+            "--crate-type",
+            "bin",
+            "-C",
+            "extra-filename=-8f3c",
+            "--out-dir",
+            "/target/build",
+        ]);
+        assert!(
+            hot_crate_operation(&build_script, "gameplay", 2, &hot_crates)
+                .unwrap()
+                .is_none()
+        );
+
+        let tests = args(&[
+            "--crate-name",
+            "gameplay",
+            "--test",
+            "-C",
+            "extra-filename=-8f3c",
+            "--out-dir",
+            "/target/deps",
+        ]);
+        assert!(hot_crate_operation(&tests, "gameplay", 2, &hot_crates)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn points_other_crates_at_hot_crate_dylibs() {
+        let dir = test_temp_dir!();
+        let dir = Utf8PathBuf::from_path_buf(dir.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("libgameplay-8f3c.4.so"), "gameplay").unwrap();
+
+        let hot_crates = HotCrates::new(["gameplay"], Target::Linux);
+        let gameplay = format!("gameplay={dir}/libgameplay-8f3c.rlib");
+        let serde = format!("serde={dir}/libserde-1234.rlib");
+
+        let rewritten = rewrite_externs(
+            args(&[
+                "--crate-name",
+                "enemies",
+                "--extern",
+                &gameplay,
+                "--extern",
+                &serde,
+                &gameplay,
+            ]),
+            &hot_crates,
+        );
+
+        assert_eq!(
+            rewritten,
+            args(&[
+                "--crate-name",
+                "enemies",
+                "--extern",
+                &format!("gameplay={dir}/libgameplay-8f3c.4.so"),
+                "--extern",
+                &serde,
+                &gameplay,
+            ])
+        );
+    }
+}
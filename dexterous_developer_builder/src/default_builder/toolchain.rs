@@ -0,0 +1,77 @@
+use anyhow::bail;
+use camino::{Utf8Path, Utf8PathBuf};
+use dexterous_developer_types::Target;
+use tokio::process::Command;
+
+/// The environment variable rustup reads to select a toolchain - it accepts a channel or the path to a toolchain
+pub const TOOLCHAIN_VARIABLE: &str = "RUSTUP_TOOLCHAIN";
+
+/// The directories in a toolchain that hold dynamic libraries a build can depend on,
+/// such as the standard library
+pub async fn toolchain_library_directories(
+    toolchain: Option<&str>,
+    target: &Target,
+    working_dir: Option<&Utf8PathBuf>,
+) -> anyhow::Result<Vec<Utf8PathBuf>> {
+    let sysroot = toolchain_sysroot(toolchain, working_dir).await?;
+    let lib = sysroot.join("lib");
+    Ok(vec![
+        lib.join("rustlib").join(target.as_str()).join("lib"),
+        lib,
+    ])
+}
+
+/// Asks rustc where the toolchain is installed, so channels and rustup overrides resolve the same way they do for cargo
+async fn toolchain_sysroot(
+    toolchain: Option<&str>,
+    working_dir: Option<&Utf8PathBuf>,
+) -> anyhow::Result<Utf8PathBuf> {
+    let mut rustc = Command::new("rustc");
+    rustc.arg("--print").arg("sysroot");
+    if let Some(toolchain) = toolchain {
+        rustc.env(TOOLCHAIN_VARIABLE, toolchain);
+    }
+    if let Some(working_dir) = working_dir {
+        rustc.current_dir(working_dir);
+    }
+
+    let output = rustc.output().await?;
+    if !output.status.success() {
+        bail!(
+            "Couldn't find the {} toolchain - {}",
+            toolchain.unwrap_or("default"),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let sysroot = String::from_utf8(output.stdout)?;
+    Ok(Utf8Path::new(sysroot.trim()).to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use dexterous_developer_types::Target;
+
+    use super::toolchain_library_directories;
+
+    #[tokio::test]
+    async fn finds_the_library_directories_of_a_toolchain() {
+        let target = Target::current().expect("Unknown target");
+        let directories = toolchain_library_directories(None, &target, None)
+            .await
+            .expect("Couldn't find the default toolchain");
+
+        assert!(directories.iter().all(|directory| directory.is_dir()));
+
+        let sysroot = directories[1].parent().unwrap().to_string();
+        let pinned = toolchain_library_directories(Some(&sysroot), &target, None)
+            .await
+            .expect("Couldn't find a toolchain by path");
+        assert_eq!(pinned, directories);
+
+        assert!(
+            toolchain_library_directories(Some("/definitely/not/a/toolchain"), &target, None)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -4,4 +4,10 @@ pub mod types;
 
 pub mod simple_watcher;
 
+pub mod watch_filter;
+
+pub mod build_loop;
+
 pub mod default_builder;
+
+pub mod command_builder;
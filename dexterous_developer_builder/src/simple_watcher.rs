@@ -1,4 +1,4 @@
-use std::env;
+use std::{env, sync::Arc};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use dashmap::DashMap;
@@ -7,11 +7,13 @@ use notify::{RecommendedWatcher, Watcher as NotifyWatcher};
 use tokio::sync::broadcast::{self};
 use tracing::{info, trace};
 
-use crate::types::{BuilderIncomingMessages, HashedFileRecord, Watcher, WatcherError};
+use crate::types::{hash_file, BuilderIncomingMessages, HashedFileRecord, Watcher, WatcherError};
 
 pub struct SimpleWatcher {
     channel: tokio::sync::broadcast::Sender<BuilderIncomingMessages>,
     watchers: DashMap<Utf8PathBuf, RecommendedWatcher>,
+    /// The content hashes of the files in the code directories, so saves that don't change anything can be skipped
+    code_hashes: Arc<DashMap<Utf8PathBuf, [u8; 32]>>,
 }
 
 impl Default for SimpleWatcher {
@@ -19,6 +21,7 @@ impl Default for SimpleWatcher {
         Self {
             channel: broadcast::channel(100).0,
             watchers: Default::default(),
+            code_hashes: Default::default(),
         }
     }
 }
@@ -41,14 +44,44 @@ impl Watcher for SimpleWatcher {
                 .or_try_insert_with::<WatcherError>(|| {
                     trace!("Adding watcher entry");
                     let directory = directory.clone();
+                    let watched = canonical_path(&directory);
+
+                    hash_code_files(&watched, &self.code_hashes);
 
                     let mut watcher = {
                         let channel = self.channel.clone();
-                        notify::recommended_watcher(move |_| {
-                            info!("Got Watch Event");
-                            let _ = channel.send(BuilderIncomingMessages::CodeChanged);
-                            trace!("Finished Sending Code Changed Messages");
-                        })?
+                        let code_hashes = self.code_hashes.clone();
+                        notify::recommended_watcher(
+                            move |event: Result<notify::Event, notify::Error>| {
+                                let paths = match event {
+                                    Ok(event) if event.kind.is_access() => return,
+                                    Ok(event) => {
+                                        let paths = event
+                                            .paths
+                                            .into_iter()
+                                            .filter_map(|path| {
+                                                Utf8PathBuf::from_path_buf(path).ok()
+                                            })
+                                            .map(|path| canonical_path(&path))
+                                            .filter(|path| !in_build_output(path, &watched))
+                                            .filter_map(|path| content_changed(path, &code_hashes))
+                                            .collect::<Vec<_>>();
+                                        if paths.is_empty() {
+                                            trace!("Watch event didn't change any content");
+                                            return;
+                                        }
+                                        paths
+                                    }
+                                    Err(e) => {
+                                        trace!("Watch error - {e}");
+                                        vec![]
+                                    }
+                                };
+                                info!("Got Watch Event");
+                                let _ = channel.send(BuilderIncomingMessages::CodeChanged(paths));
+                                trace!("Finished Sending Code Changed Messages");
+                            },
+                        )?
                     };
 
                     trace!("Watching Directory");
@@ -173,6 +206,73 @@ impl Watcher for SimpleWatcher {
     }
 }
 
+fn canonical_path(path: &Utf8Path) -> Utf8PathBuf {
+    if let Ok(path) = path.canonicalize_utf8() {
+        return path;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize_utf8()
+            .map(|parent| parent.join(name))
+            .unwrap_or_else(|_| path.to_owned()),
+        _ => path.to_owned(),
+    }
+}
+
+/// Records the hashes of every file in a code directory, without replacing any that are already known
+fn hash_code_files(path: &Utf8Path, code_hashes: &DashMap<Utf8PathBuf, [u8; 32]>) {
+    let path = canonical_path(path);
+    if path.is_file() {
+        if let Ok(hash) = hash_file(&path) {
+            code_hashes.entry(path).or_insert(hash);
+        }
+    } else if let Ok(entries) = path.read_dir_utf8() {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            if entry.path().is_dir() && is_build_output(entry.path()) {
+                trace!("Not hashing build output in {}", entry.path());
+                continue;
+            }
+            hash_code_files(entry.path(), code_hashes);
+        }
+    }
+}
+
+/// Whether a directory holds cargo's build output - either named `target`, or tagged by cargo as a cache
+fn is_build_output(directory: &Utf8Path) -> bool {
+    directory.file_name() == Some("target") || directory.join("CACHEDIR.TAG").is_file()
+}
+
+/// Whether a path below a watched directory is inside cargo's build output, so changes to it can be ignored
+fn in_build_output(path: &Utf8Path, watched: &Utf8Path) -> bool {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != watched && ancestor.starts_with(watched))
+        .any(is_build_output)
+}
+
+/// Checks a path from a watch event against its last known content, returning it if it changed
+fn content_changed(
+    path: Utf8PathBuf,
+    code_hashes: &DashMap<Utf8PathBuf, [u8; 32]>,
+) -> Option<Utf8PathBuf> {
+    let path = canonical_path(&path);
+    if path.is_file() {
+        let Ok(hash) = hash_file(&path) else {
+            return Some(path);
+        };
+        if code_hashes.insert(path.clone(), hash) == Some(hash) {
+            None
+        } else {
+            Some(path)
+        }
+    } else if path.exists() {
+        Some(path)
+    } else {
+        let known = code_hashes.len();
+        code_hashes.retain(|known_path, _| !known_path.starts_with(&path));
+        (code_hashes.len() != known).then_some(path)
+    }
+}
+
 fn gather_directory_content(
     dir: Utf8PathBuf,
     cwd: &Utf8Path,
@@ -266,7 +366,114 @@ mod test {
             .expect("Didn't recieve watcher message on time")
             .expect("Didn't recieve watcher message");
 
-        assert!(matches!(result, BuilderIncomingMessages::CodeChanged));
+        assert!(matches!(result, BuilderIncomingMessages::CodeChanged(_)));
+    }
+
+    #[tokio::test]
+    async fn watcher_only_reports_code_files_whose_content_changed() {
+        let dir = test_temp_dir!();
+        let path = dir.as_path_untracked().join("lib.rs");
+        write(&path, "fn main() {}")
+            .await
+            .expect("Couldn't write file");
+
+        let watcher = SimpleWatcher::default();
+
+        let mut rx = watcher.channel.subscribe();
+
+        watcher
+            .watch_code_directories(&[Utf8PathBuf::from_path_buf(
+                dir.as_path_untracked().to_path_buf(),
+            )
+            .unwrap()])
+            .expect("Couldn't set up watcher on temporary directory");
+
+        {
+            let mut file = File::options()
+                .write(true)
+                .open(&path)
+                .await
+                .expect("Couldn't open file");
+            file.write_all("fn main() {}".as_bytes())
+                .await
+                .expect("Couldn't write file");
+        }
+
+        let result = timeout(Duration::from_millis(100), rx.recv()).await;
+        assert!(
+            result.is_err(),
+            "Rewriting identical content triggered a change"
+        );
+
+        write(&path, "fn main() { println!(); }")
+            .await
+            .expect("Couldn't write file");
+
+        let result = timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .expect("Didn't recieve watcher message on time")
+            .expect("Didn't recieve watcher message");
+
+        let BuilderIncomingMessages::CodeChanged(paths) = result else {
+            panic!("Got Message that isn't Code Changed");
+        };
+        assert_eq!(
+            paths,
+            vec![Utf8PathBuf::from_path_buf(path.canonicalize().unwrap()).unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn watcher_skips_the_cargo_target_directory() {
+        let dir = test_temp_dir!();
+        let root =
+            Utf8PathBuf::from_path_buf(dir.as_path_untracked().canonicalize().unwrap()).unwrap();
+        let output = root.join("target").join("debug");
+        create_dir_all(&output)
+            .await
+            .expect("Couldn't create target directory");
+        write(root.join("target").join("CACHEDIR.TAG"), "")
+            .await
+            .expect("Couldn't write file");
+        write(output.join("libgame.so"), "library")
+            .await
+            .expect("Couldn't write file");
+        let source = root.join("lib.rs");
+        write(&source, "fn main() {}")
+            .await
+            .expect("Couldn't write file");
+
+        let watcher = SimpleWatcher::default();
+
+        let mut rx = watcher.channel.subscribe();
+
+        watcher
+            .watch_code_directories(&[root])
+            .expect("Couldn't set up watcher on temporary directory");
+
+        assert!(watcher.code_hashes.contains_key(&source));
+        assert!(!watcher.code_hashes.contains_key(&output.join("libgame.so")));
+
+        write(output.join("libgame.so"), "rebuilt library")
+            .await
+            .expect("Couldn't write file");
+
+        let result = timeout(Duration::from_millis(100), rx.recv()).await;
+        assert!(result.is_err(), "Build output triggered a change");
+
+        write(&source, "fn main() { println!(); }")
+            .await
+            .expect("Couldn't write file");
+
+        let result = timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .expect("Didn't recieve watcher message on time")
+            .expect("Didn't recieve watcher message");
+
+        let BuilderIncomingMessages::CodeChanged(paths) = result else {
+            panic!("Got Message that isn't Code Changed");
+        };
+        assert_eq!(paths, vec![source]);
     }
 
     #[tokio::test]
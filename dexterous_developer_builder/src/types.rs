@@ -1,12 +1,17 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc,
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 
-use camino::{FromPathBufError, Utf8PathBuf};
+use camino::{FromPathBufError, Utf8Path, Utf8PathBuf};
 
 use dashmap::DashMap;
-use dexterous_developer_types::{BuilderTypes, Target};
+use dexterous_developer_types::{
+    BuildDiagnostic, BuildProgress, BuilderTypes, PhaseTiming, Target,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::Mutex;
@@ -32,6 +37,8 @@ pub trait Builder: 'static + Send + Sync {
     fn root_lib_name(&self) -> Option<String>;
     fn get_code_subscriptions(&self) -> Vec<Utf8PathBuf>;
     fn get_asset_subscriptions(&self) -> Vec<Utf8PathBuf>;
+    /// A build completed before the builder started, such as one from an earlier session
+    fn last_completed_build(&self) -> Option<CompletedBuild>;
 }
 
 pub trait Watcher: 'static + Send + Sync {
@@ -59,14 +66,22 @@ pub enum WatcherError {
 #[derive(Debug, Clone)]
 pub enum BuilderIncomingMessages {
     RequestBuild(Target),
-    CodeChanged,
+    /// Code in a watched folder changed - contains the changed paths, if the watcher knows them
+    CodeChanged(Vec<Utf8PathBuf>),
     AssetChanged(HashedFileRecord),
+    /// The builds that connected runners currently have loaded for a target
+    LoadedBuilds {
+        target: Target,
+        build_ids: HashSet<u32>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub enum BuilderOutgoingMessages {
     Waiting,
     BuildStarted,
+    /// Folders the builder found code in after starting, which the watcher should also watch
+    WatchCode(Vec<Utf8PathBuf>),
 }
 
 #[derive(Clone, Debug, Default)]
@@ -79,6 +94,13 @@ pub struct CurrentBuildState {
     pub builder_type: BuilderTypes,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletedBuild {
+    pub id: u32,
+    pub libraries: Vec<HashedFileRecord>,
+    pub root_library: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HashedFileRecord {
     pub relative_path: Utf8PathBuf,
@@ -105,16 +127,38 @@ impl HashedFileRecord {
     }
 }
 
+/// The blake3 hash of a file's content
+pub fn hash_file(path: &Utf8Path) -> std::io::Result<[u8; 32]> {
+    let file = std::fs::read(path)?;
+    Ok(blake3::hash(&file).as_bytes().to_owned())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum BuildOutputMessages {
     StartedBuild(u32),
+    /// `libraries` holds the libraries that changed since the previous build,
+    /// and `unchanged` names the ones from earlier builds that are still part of this one
     EndedBuild {
         id: u32,
         libraries: Vec<HashedFileRecord>,
+        unchanged: Vec<String>,
         root_library: String,
     },
     AssetUpdated(HashedFileRecord),
     FailedBuild(String),
+    /// The build with this id was stopped before finishing, because a newer build replaced it
+    CancelledBuild(u32),
+    /// Errors and warnings reported by the compiler while the build with this id runs
+    Diagnostics {
+        id: u32,
+        diagnostics: Vec<BuildDiagnostic>,
+    },
+    BuildProgress(BuildProgress),
+    /// How long each phase of a completed build took
+    Timings {
+        id: u32,
+        timings: Vec<PhaseTiming>,
+    },
     KeepAlive,
 }
 
@@ -130,6 +174,21 @@ impl CurrentBuildState {
         }
     }
 
+    /// Creates a state that already serves a completed build
+    pub fn from_completed_build(build: CompletedBuild, builder_type: BuilderTypes) -> Self {
+        let state = Self::new(Some(build.root_library), builder_type);
+        for record in build.libraries.into_iter() {
+            state.libraries.insert(record.relative_path.clone(), record);
+        }
+        state
+            .most_recent_completed_build
+            .store(build.id, Ordering::SeqCst);
+        state
+            .most_recent_started_build
+            .store(build.id, Ordering::SeqCst);
+        state
+    }
+
     pub async fn update(&self, msg: BuildOutputMessages) -> &Self {
         match msg {
             BuildOutputMessages::AssetUpdated(record) => {
@@ -143,8 +202,16 @@ impl CurrentBuildState {
             BuildOutputMessages::EndedBuild {
                 id,
                 libraries,
+                unchanged,
                 root_library,
             } => {
+                let names = libraries
+                    .iter()
+                    .map(|record| record.name.clone())
+                    .chain(unchanged)
+                    .collect::<HashSet<_>>();
+                self.libraries
+                    .retain(|_, record| names.contains(&record.name));
                 for record in libraries.into_iter() {
                     self.libraries.insert(record.relative_path.clone(), record);
                 }
@@ -154,6 +221,10 @@ impl CurrentBuildState {
                 let _ = lock.replace(root_library);
             }
             BuildOutputMessages::FailedBuild(_) => {}
+            BuildOutputMessages::CancelledBuild(_) => {}
+            BuildOutputMessages::Diagnostics { .. } => {}
+            BuildOutputMessages::BuildProgress(_) => {}
+            BuildOutputMessages::Timings { .. } => {}
         }
         self
     }
@@ -165,7 +236,9 @@ mod test {
 
     use camino::Utf8PathBuf;
 
-    use super::{BuildOutputMessages, CurrentBuildState, HashedFileRecord};
+    use dexterous_developer_types::BuilderTypes;
+
+    use super::{BuildOutputMessages, CompletedBuild, CurrentBuildState, HashedFileRecord};
 
     #[tokio::test]
     async fn current_build_state_can_update_asset_record() {
@@ -224,6 +297,7 @@ mod test {
                     hash: Default::default(),
                     dependencies: vec![],
                 }],
+                unchanged: vec![],
                 root_library: "Root".to_string(),
             })
             .await;
@@ -242,6 +316,86 @@ mod test {
         assert_eq!(state.most_recent_completed_build.load(Ordering::SeqCst), 1);
     }
 
+    #[tokio::test]
+    async fn ending_a_build_keeps_unchanged_libraries_and_drops_removed_ones() {
+        let state = CurrentBuildState::default();
+
+        let _ = state
+            .update(BuildOutputMessages::EndedBuild {
+                id: 1,
+                libraries: vec![
+                    HashedFileRecord::new(
+                        "./libgame.1.so",
+                        "/libgame.1.so",
+                        "libgame.1.so",
+                        [1; 32],
+                    ),
+                    HashedFileRecord::new("./libstd.so", "/libstd.so", "libstd.so", [2; 32]),
+                    HashedFileRecord::new("./libold.so", "/libold.so", "libold.so", [3; 32]),
+                ],
+                unchanged: vec![],
+                root_library: "libgame.1.so".to_string(),
+            })
+            .await;
+
+        let _ = state
+            .update(BuildOutputMessages::EndedBuild {
+                id: 2,
+                libraries: vec![HashedFileRecord::new(
+                    "./libgame.2.so",
+                    "/libgame.2.so",
+                    "libgame.2.so",
+                    [4; 32],
+                )],
+                unchanged: vec!["libstd.so".to_string()],
+                root_library: "libgame.2.so".to_string(),
+            })
+            .await;
+
+        let mut names = state
+            .libraries
+            .iter()
+            .map(|record| record.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["libgame.2.so", "libstd.so"]);
+    }
+
+    #[tokio::test]
+    async fn a_restored_build_is_served_until_a_newer_one_completes() {
+        let state = CurrentBuildState::from_completed_build(
+            CompletedBuild {
+                id: 7,
+                libraries: vec![HashedFileRecord::new(
+                    "./library",
+                    "/local/path",
+                    "library",
+                    [0; 32],
+                )],
+                root_library: "Root".to_string(),
+            },
+            BuilderTypes::Default,
+        );
+
+        assert!(state
+            .libraries
+            .contains_key(&Utf8PathBuf::from("./library")));
+        assert_eq!(state.most_recent_completed_build.load(Ordering::SeqCst), 7);
+        assert_eq!(state.most_recent_started_build.load(Ordering::SeqCst), 7);
+
+        let _ = state
+            .update(BuildOutputMessages::EndedBuild {
+                id: 8,
+                libraries: vec![],
+                unchanged: vec![],
+                root_library: "New Root".to_string(),
+            })
+            .await;
+
+        assert_eq!(state.most_recent_completed_build.load(Ordering::SeqCst), 8);
+        assert_eq!(state.root_library.lock().await.as_deref(), Some("New Root"));
+    }
+
     #[tokio::test]
     async fn ending_a_previous_build_after_a_newer_one_doesnt_update_current_state() {
         let state = CurrentBuildState::default();
@@ -250,6 +404,7 @@ mod test {
             .update(BuildOutputMessages::EndedBuild {
                 id: 2,
                 libraries: vec![],
+                unchanged: vec![],
                 root_library: String::default(),
             })
             .await;
@@ -260,6 +415,7 @@ mod test {
             .update(BuildOutputMessages::EndedBuild {
                 id: 1,
                 libraries: vec![],
+                unchanged: vec![],
                 root_library: String::default(),
             })
             .await;
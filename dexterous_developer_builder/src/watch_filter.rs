@@ -0,0 +1,102 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Decides whether a code change is relevant to a specific builder.
+///
+/// The watcher is shared between every builder, so each builder checks the changed paths
+/// against its own code watch folders and ignore globs before rebuilding.
+#[derive(Debug, Clone)]
+pub struct CodeWatchFilter {
+    folders: Vec<Utf8PathBuf>,
+    ignore: GlobSet,
+}
+
+impl CodeWatchFilter {
+    pub fn new(folders: &[Utf8PathBuf], ignore: &[String]) -> Result<Self, globset::Error> {
+        let folders = folders
+            .iter()
+            .map(|folder| {
+                folder
+                    .canonicalize_utf8()
+                    .unwrap_or_else(|_| folder.clone())
+            })
+            .collect();
+
+        let mut builder = GlobSetBuilder::new();
+        for pattern in ignore {
+            builder.add(Glob::new(pattern)?);
+        }
+
+        Ok(Self {
+            folders,
+            ignore: builder.build()?,
+        })
+    }
+
+    /// The folders changes are watched in, canonicalized where possible
+    pub fn folders(&self) -> &[Utf8PathBuf] {
+        &self.folders
+    }
+
+    /// Returns true if any of the changed paths should trigger a rebuild.
+    ///
+    /// If no paths are provided, or no folders are configured, the change is assumed to be relevant.
+    pub fn is_relevant(&self, paths: &[Utf8PathBuf]) -> bool {
+        paths.is_empty() || paths.iter().any(|path| self.is_relevant_path(path))
+    }
+
+    fn is_relevant_path(&self, path: &Utf8Path) -> bool {
+        let relative = if self.folders.is_empty() {
+            path
+        } else {
+            let Some(folder) = self.folders.iter().find(|folder| path.starts_with(folder)) else {
+                return false;
+            };
+            path.strip_prefix(folder).unwrap_or(path)
+        };
+        !(self.ignore.is_match(path) || self.ignore.is_match(relative))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changes_outside_the_watch_folders_are_not_relevant() {
+        let filter = CodeWatchFilter::new(&[Utf8PathBuf::from("/project/game/src")], &[])
+            .expect("Couldn't create filter");
+
+        assert!(filter.is_relevant(&[Utf8PathBuf::from("/project/game/src/lib.rs")]));
+        assert!(!filter.is_relevant(&[Utf8PathBuf::from("/project/editor/src/lib.rs")]));
+        assert!(filter.is_relevant(&[
+            Utf8PathBuf::from("/project/editor/src/lib.rs"),
+            Utf8PathBuf::from("/project/game/src/main.rs")
+        ]));
+    }
+
+    #[test]
+    fn ignored_files_are_not_relevant() {
+        let filter = CodeWatchFilter::new(
+            &[Utf8PathBuf::from("/project/game")],
+            &["*.md".to_string(), "src/generated/**".to_string()],
+        )
+        .expect("Couldn't create filter");
+
+        assert!(filter.is_relevant(&[Utf8PathBuf::from("/project/game/src/lib.rs")]));
+        assert!(!filter.is_relevant(&[Utf8PathBuf::from("/project/game/README.md")]));
+        assert!(
+            !filter.is_relevant(&[Utf8PathBuf::from("/project/game/src/generated/bindings.rs")])
+        );
+    }
+
+    #[test]
+    fn without_watch_folders_everything_but_ignored_files_is_relevant() {
+        let filter =
+            CodeWatchFilter::new(&[], &["**/*.md".to_string()]).expect("Couldn't create filter");
+
+        assert!(filter.is_relevant(&[Utf8PathBuf::from("/project/game/src/lib.rs")]));
+        assert!(!filter.is_relevant(&[Utf8PathBuf::from("/project/game/README.md")]));
+        assert!(filter.is_relevant(&[]));
+    }
+}
@@ -0,0 +1,148 @@
+use camino::Utf8Path;
+use cargo_metadata::{Metadata, Package};
+use dexterous_developer_types::{
+    config::{DexterousConfig, ExplainedBuildSettings, LoadConfigError, SettingSource},
+    PackageOrExample,
+};
+use tracing::{trace, warn};
+
+/// Loads the layered config for the package or example being built,
+/// using `cargo metadata` to find the workspace root, the package directory,
+/// and any config in `[workspace.metadata.dexterous]` or `[package.metadata.dexterous]`.
+///
+/// In strict mode, unknown keys in any layer are reported as errors.
+pub async fn load_config(
+    current_directory: &Utf8Path,
+    metadata: Option<&Metadata>,
+    package_or_example: &PackageOrExample,
+    strict: bool,
+) -> Result<DexterousConfig, LoadConfigError> {
+    let Some(metadata) = metadata else {
+        warn!("Couldn't get cargo metadata - only loading config from {current_directory}");
+        return DexterousConfig::load_layered_with_metadata(
+            current_directory,
+            None,
+            None,
+            None,
+            strict,
+        )
+        .await;
+    };
+
+    let from_cargo_metadata = if strict {
+        DexterousConfig::from_cargo_metadata_strict
+    } else {
+        DexterousConfig::from_cargo_metadata
+    };
+
+    let workspace_metadata = from_cargo_metadata(&metadata.workspace_metadata)?;
+
+    let package = find_package(metadata, package_or_example);
+    let package_directory = package.and_then(|p| p.manifest_path.parent());
+    let package_metadata = package
+        .map(|p| from_cargo_metadata(&p.metadata))
+        .transpose()?
+        .flatten();
+
+    trace!(
+        "Loading config for workspace {} and package directory {package_directory:?}",
+        metadata.workspace_root
+    );
+
+    DexterousConfig::load_layered_with_metadata(
+        &metadata.workspace_root,
+        workspace_metadata,
+        package_directory,
+        package_metadata,
+        strict,
+    )
+    .await
+}
+
+fn find_package<'a>(
+    metadata: &'a Metadata,
+    package_or_example: &PackageOrExample,
+) -> Option<&'a Package> {
+    match package_or_example {
+        PackageOrExample::DefaulPackage => metadata.root_package().or_else(|| {
+            if metadata.workspace_default_members.len() != 1 {
+                return None;
+            }
+            let default_member = metadata.workspace_default_members.first()?;
+            metadata.packages.iter().find(|p| p.id == *default_member)
+        }),
+        PackageOrExample::Package(package) => metadata.packages.iter().find(|p| p.name == *package),
+        PackageOrExample::Example(example) => metadata.packages.iter().find(|p| {
+            p.targets
+                .iter()
+                .any(|t| t.is_example() && t.name == *example)
+        }),
+    }
+}
+
+/// Prints the output of `config explain`
+pub fn print_explanation(explained: &[ExplainedBuildSettings]) {
+    for explained in explained {
+        let settings = &explained.settings;
+        println!("[{}]", explained.target);
+        print_setting(
+            explained,
+            "package_or_example",
+            &settings.package_or_example,
+        );
+        print_setting(explained, "features", &settings.features);
+        print_setting(explained, "asset_folders", &settings.asset_folders);
+        print_setting(
+            explained,
+            "code_watch_folders",
+            &settings.code_watch_folders,
+        );
+        print_setting(explained, "ignore", &settings.ignore);
+        let mut environment = settings.environment.iter().collect::<Vec<_>>();
+        environment.sort();
+        print_setting(explained, "environment", &environment);
+        print_setting(explained, "builder", &settings.builder);
+        print_setting(explained, "manifest_path", &settings.manifest_path);
+        print_setting(
+            explained,
+            "additional_library_directories",
+            &settings.additional_library_directories,
+        );
+        print_setting(
+            explained,
+            "apple_sdk_directory",
+            &settings.apple_sdk_directory,
+        );
+        print_setting(explained, "cranelift", &settings.craneflift);
+        print_setting(
+            explained,
+            "cancel_stale_builds",
+            &settings.cancel_stale_builds,
+        );
+        print_setting(explained, "profile", &settings.cargo_profile());
+        print_setting(explained, "keep_builds", &settings.builds_to_keep());
+        print_setting(explained, "linker", &settings.linker_for(&explained.target));
+        print_setting(explained, "toolchain", &settings.rust_toolchain());
+        print_setting(explained, "hot_crates", &settings.hot_crates);
+        print_setting(explained, "working_dir", &settings.working_dir);
+
+        for warning in explained.warnings.iter() {
+            println!("  warning: {warning}");
+        }
+        println!();
+    }
+}
+
+fn print_setting(explained: &ExplainedBuildSettings, field: &str, value: &impl std::fmt::Debug) {
+    let sources = explained.sources_for(field);
+    let sources = if sources.is_empty() {
+        SettingSource::Default.to_string()
+    } else {
+        sources
+            .iter()
+            .map(|source| source.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    println!("  {field} = {value:?}    # from {sources}");
+}
@@ -1,40 +1,84 @@
-use std::{env, process, sync::Arc};
+mod config;
+
+use std::{collections::HashSet, env, process, sync::Arc};
 
 use camino::Utf8PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dexterous_developer_builder::{
-    default_builder::builder::DefaultBuilderInitializer, simple_watcher::SimpleWatcher,
+    command_builder::CommandBuilderInitializer,
+    default_builder::{
+        builder::{cargo_metadata, DefaultBuilderInitializer},
+        cleanup::{output_directory, remove_old_builds},
+        hot_crates::hot_crate_directory,
+        linker::select_linker,
+    },
+    simple_watcher::SimpleWatcher,
 };
 use dexterous_developer_manager::{server::run_server, Manager};
-use dexterous_developer_types::{config::DexterousConfig, PackageOrExample, Target};
+use dexterous_developer_types::{
+    config::DexterousConfig, profile_directory, PackageOrExample, Target,
+};
 use tracing::{info, trace};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Package to build (required in a workspace)
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     package: Option<String>,
 
     /// Example to build
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     example: Option<String>,
 
     /// Features to include
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     features: Vec<String>,
 
-    /// Port to host on
-    #[arg(default_value = "1234")]
-    port: u16,
+    /// Profile from the [profiles] section of Dexterous.toml to apply
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Fail on unknown keys in Dexterous.toml or the Cargo.toml metadata, instead of ignoring them
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Port to host on - defaults to the port set in Dexterous.toml, or 1234
+    port: Option<u16>,
 
     /// Do not run the application localy
     #[arg(short, long)]
     serve_only: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Inspect the Dexterous.toml config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Remove the numbered libraries left behind by earlier hot reload builds
+    Clean {
+        /// Remove all of target/hot-reload instead, so the next build starts from scratch
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Print the resolved build settings for each target, and where each setting came from
+    Explain,
+    /// Print a JSON Schema for Dexterous.toml, for editors to validate against
+    Schema,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::registry()
@@ -43,19 +87,28 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let Args {
+        command,
         package,
         example,
         features,
+        profile,
+        strict,
         port,
         serve_only,
     } = Args::parse();
 
+    if let Some(Commands::Config {
+        command: ConfigCommands::Schema,
+    }) = command
+    {
+        let schema = serde_json::to_string_pretty(&DexterousConfig::json_schema())?;
+        println!("{schema}");
+        return Ok(());
+    }
+
     let current_directory =
         Utf8PathBuf::try_from(env::current_dir().expect("Couldn't get current directory"))
             .expect("Couldn't parse current directory");
-    let config = DexterousConfig::load_toml(&current_directory)
-        .await
-        .expect("Couldn't load config");
 
     let package_or_example = match (package, example) {
         (None, None) => PackageOrExample::DefaulPackage,
@@ -64,20 +117,97 @@ async fn main() -> anyhow::Result<()> {
         (Some(_), Some(_)) => panic!("Can only build either a package or an example, not both"),
     };
 
+    // Read once here, and shared with the builders so they don't need to run cargo again
+    let metadata = cargo_metadata(None, Some(&current_directory), None)
+        .await
+        .map_err(|e| trace!("Cargo metadata failed - {e}"))
+        .ok();
+
+    let config = match config::load_config(
+        &current_directory,
+        metadata.as_ref(),
+        &package_or_example,
+        strict,
+    )
+    .await
+    {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Couldn't load config - {e}");
+            process::exit(1);
+        }
+    };
+
+    if let Some(Commands::Config {
+        command: ConfigCommands::Explain,
+    }) = command
+    {
+        let explained = config
+            .explain_build_settings(Some(package_or_example), &features, profile.as_deref())
+            .expect("Failed determine build settings");
+        config::print_explanation(&explained);
+        return Ok(());
+    }
+
+    let port = port.or(config.port).unwrap_or(1234);
+
     trace!("Setting up builders for {package_or_example:?}");
 
     let builder_settings = config
-        .generate_build_settings(Some(package_or_example.clone()), &features)
+        .generate_build_settings(
+            Some(package_or_example.clone()),
+            &features,
+            profile.as_deref(),
+        )
         .expect("Failed determine build settings");
 
+    if let Some(Commands::Clean { all }) = command {
+        if all {
+            let directory = Utf8PathBuf::from("./target/hot-reload");
+            if directory.exists() {
+                std::fs::remove_dir_all(&directory)?;
+            }
+            println!("Removed {directory}");
+        } else {
+            for (target, settings) in builder_settings.iter() {
+                let output = output_directory(target, settings.cargo_profile());
+                for directory in [output.clone(), hot_crate_directory(&output)] {
+                    let removed = remove_old_builds(&directory, 0, &HashSet::new())?;
+                    println!("Removed {} old libraries from {directory}", removed.len());
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let library_directory = builder_settings
+        .iter()
+        .find(|(target, _)| Some(target) == Target::current().as_ref())
+        .map(|(_, settings)| profile_directory(settings.cargo_profile()).to_string())
+        .unwrap_or_else(|| profile_directory("dev").to_string());
+
     trace!("Setting up Manager");
 
     let mut manager = Manager::new(Arc::new(SimpleWatcher::default()));
 
-    for (target, build_settings) in builder_settings.into_iter() {
+    for (target, mut build_settings) in builder_settings.into_iter() {
+        build_settings.linker = Some(select_linker(&target, &build_settings));
         manager = match build_settings.builder {
             dexterous_developer_types::BuilderTypes::Default => {
-                manager.add_builder(DefaultBuilderInitializer::new(target, build_settings))
+                // The metadata only applies if the builder would read it for the same workspace
+                let shared_metadata = metadata.as_ref().filter(|_| {
+                    build_settings.manifest_path.is_none()
+                        && build_settings.working_dir.is_none()
+                        && build_settings.rust_toolchain().is_none()
+                });
+                let mut initializer = DefaultBuilderInitializer::new(target, build_settings);
+                if let Some(metadata) = shared_metadata {
+                    initializer = initializer.with_metadata(metadata.clone());
+                }
+                manager.add_builder(initializer)
+            }
+            dexterous_developer_types::BuilderTypes::Command => {
+                manager.add_builder(CommandBuilderInitializer::new(target, build_settings))
             }
         }?;
     }
@@ -96,7 +226,9 @@ async fn main() -> anyhow::Result<()> {
             cmd.arg("--working-directory")
                 .arg(&current_directory)
                 .arg("--library-path")
-                .arg(current_directory.join(format!("./target/hot-reload/{target}/{target}/debug")))
+                .arg(current_directory.join(format!(
+                    "./target/hot-reload/{target}/{target}/{library_directory}"
+                )))
                 .arg("--in-workspace");
 
             let mut child = cmd.spawn().expect("Couldn't execute runner");
@@ -1,5 +1,5 @@
 use camino::Utf8PathBuf;
-use dexterous_developer_types::BuilderTypes;
+use dexterous_developer_types::{BuildDiagnostic, BuildProgress, BuilderTypes};
 
 #[derive(Debug, Clone)]
 pub enum DylibRunnerMessage {
@@ -8,6 +8,9 @@ pub enum DylibRunnerMessage {
         build_id: u32,
         local_path: Utf8PathBuf,
         builder_type: BuilderTypes,
+        /// Libraries that changed in this build, such as hot crates, in the order they need to be loaded -
+        /// each one after the libraries it depends on
+        dependencies: Vec<Utf8PathBuf>,
     },
     AssetUpdated {
         local_path: Utf8PathBuf,
@@ -16,6 +19,11 @@ pub enum DylibRunnerMessage {
     SerializedMessage {
         message: Vec<u8>,
     },
+    Diagnostics {
+        build_id: u32,
+        diagnostics: Vec<BuildDiagnostic>,
+    },
+    BuildProgress(BuildProgress),
 }
 
 #[derive(Debug, Clone)]
@@ -19,8 +19,9 @@ pub enum DylibRunnerError {
     WorkingDirectoryDoesntExist(Utf8PathBuf),
     #[error("Library Directory does not exist - {0:?}")]
     LibraryDirectoryDoesntExist(Utf8PathBuf),
+    /// Boxed, since websocket errors are far larger than any other variant
     #[error("WebSocket Error {0}")]
-    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    WebSocketError(Box<tokio_tungstenite::tungstenite::Error>),
     #[error("RMP Parse Error {0}")]
     RmpParseError(#[from] rmp_serde::decode::Error),
     #[error("RMP Encoder Error {0}")]
@@ -28,7 +29,7 @@ pub enum DylibRunnerError {
     #[error("Async Channel Failed {0}")]
     AsyncChannelError(#[from] async_channel::RecvError),
     #[error("Join Handle Failed")]
-    JoinHandleFailed(std::boxed::Box<(dyn std::any::Any + std::marker::Send + 'static)>),
+    JoinHandleFailed(std::boxed::Box<dyn std::any::Any + std::marker::Send + 'static>),
     #[error("Library Holder Error {0}")]
     LibraryError(#[from] dexterous_developer_instance::library_holder::LibraryError),
     #[error("Couldn't Open Initial Library")]
@@ -37,6 +38,21 @@ pub enum DylibRunnerError {
     OnceCellError,
     #[error("Download Failed: {0}")]
     DownloadError(#[from] reqwest::Error),
+    #[error("The server rejected the connection (server protocol version {server_protocol_version}) - {reason}")]
+    HandshakeRejected {
+        server_protocol_version: u32,
+        reason: String,
+    },
+    #[error("The server uses protocol version {0}, but this runner supports versions {min} to {max}", min = dexterous_developer_types::MIN_SUPPORTED_PROTOCOL_VERSION, max = dexterous_developer_types::PROTOCOL_VERSION)]
+    IncompatibleServer(u32),
+    #[error("The server didn't complete the handshake - it is likely from an older release: {0}")]
+    HandshakeFailed(String),
     #[error("Couldn'y Determine Downloaded Asset Directory: {0}")]
     NoAssedDirectory(Utf8PathBuf),
 }
+
+impl From<tokio_tungstenite::tungstenite::Error> for DylibRunnerError {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocketError(Box::new(error))
+    }
+}
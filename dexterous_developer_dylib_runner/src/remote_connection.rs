@@ -1,27 +1,36 @@
 use std::{
+    collections::{HashMap, HashSet},
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc,
     },
     thread::JoinHandle,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use camino::{Utf8Path, Utf8PathBuf};
-use dexterous_developer_types::{BuilderTypes, HotReloadMessage, Target};
-use futures_util::StreamExt;
+use dexterous_developer_types::{
+    BuildPhase, BuilderTypes, Capability, ClientHandshake, DiagnosticLevel, HotReloadMessage,
+    PhaseTiming, RunnerMessage, ServerHandshake, Target, MIN_SUPPORTED_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use futures_util::{SinkExt, Stream, StreamExt};
 use tokio::{io::AsyncWriteExt, time::sleep};
-use tokio_tungstenite::connect_async;
-use tracing::{error, info, trace, warn};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{debug, error, info, trace, warn};
 use url::Url;
 
-use crate::{dylib_runner_message::DylibRunnerMessage, error::DylibRunnerError};
+use crate::{
+    dylib_runner_message::{DylibRunnerMessage, DylibRunnerOutput},
+    error::DylibRunnerError,
+};
 
 pub fn connect_to_server(
     working_directory: &Utf8Path,
     library_path: &Utf8Path,
     server: url::Url,
     tx: async_channel::Sender<DylibRunnerMessage>,
+    out_rx: async_channel::Receiver<DylibRunnerOutput>,
     in_workspace: bool,
 ) -> Result<JoinHandle<Result<(), DylibRunnerError>>, DylibRunnerError> {
     let current_target = Target::current().ok_or(DylibRunnerError::NoCurrentTarget)?;
@@ -65,6 +74,7 @@ pub fn connect_to_server(
                     server,
                     target,
                     tx.clone(),
+                    out_rx,
                     library_path,
                     working_directory,
                     in_workspace,
@@ -79,11 +89,13 @@ pub fn connect_to_server(
     }))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn remote_connection(
     address: Url,
     server: Url,
     target: Target,
     tx: async_channel::Sender<DylibRunnerMessage>,
+    out_rx: async_channel::Receiver<DylibRunnerOutput>,
     library_path: Utf8PathBuf,
     working_directory: Utf8PathBuf,
     in_workspace: bool,
@@ -97,7 +109,15 @@ pub(crate) async fn remote_connection(
 
     info!("Connected");
 
-    let (_, mut read) = ws_stream.split();
+    let (mut write, mut read) = ws_stream.split();
+
+    write
+        .send(Message::Binary(rmp_serde::to_vec(
+            &ClientHandshake::default(),
+        )?))
+        .await?;
+    let capabilities = receive_handshake(&mut read).await?;
+    info!("Handshake completed with capabilities {capabilities:?}");
 
     let (download_tx, mut download_rx) = tokio::sync::mpsc::unbounded_channel::<DownloadResult>();
 
@@ -107,10 +127,43 @@ pub(crate) async fn remote_connection(
     let mut root_lib_path: Option<Utf8PathBuf> = None;
     let mut root_lib_name: Option<String> = None;
     let mut builder_type: Option<BuilderTypes> = None;
+    // The libraries the runner has been told about, which builds can refer to as unchanged
+    let mut known_libraries: HashSet<String> = HashSet::new();
+    // The libraries that changed in the most recent build and what they depend on, and where they were downloaded to
+    let mut changed_libraries: HashMap<String, Vec<String>> = HashMap::new();
+    let mut downloaded_libraries: HashMap<String, Utf8PathBuf> = HashMap::new();
     let pending_downloads = Arc::new(AtomicU32::new(0));
+    let report_timings = capabilities.contains(&Capability::Timings);
+    // When the libraries for the most recent build started downloading
+    let mut download_started: Option<Instant> = None;
+    // The build that was sent to be loaded, how long its download took, and when loading started
+    let mut load_started: Option<(u32, Duration, Instant)> = None;
 
     loop {
         tokio::select! {
+            Ok(output) = out_rx.recv() => {
+                match output {
+                    DylibRunnerOutput::LoadedLib { build_id } => {
+                        trace!("Reporting that build {build_id} is loaded");
+                        let message = rmp_serde::to_vec(&RunnerMessage::LoadedBuild(build_id))?;
+                        write.send(Message::Binary(message)).await?;
+
+                        if let Some((_, download, started)) = load_started.filter(|(id, ..)| *id == build_id) {
+                            load_started = None;
+                            let timings = vec![
+                                PhaseTiming::new(BuildPhase::Download, download),
+                                PhaseTiming::new(BuildPhase::Load, started.elapsed()),
+                            ];
+                            debug!("Build {build_id} timings - {timings:?}");
+                            if report_timings {
+                                let message = rmp_serde::to_vec(&RunnerMessage::Timings { build_id, timings })?;
+                                write.send(Message::Binary(message)).await?;
+                            }
+                        }
+                    }
+                    DylibRunnerOutput::SerializedMessage { .. } => {}
+                }
+            }
             Some(result) = download_rx.recv() => {
                 match result {
                         DownloadResult::Downloaded { name, local_path, is_asset } => {
@@ -127,6 +180,7 @@ pub(crate) async fn remote_connection(
                                 root_lib_path = Some(local_path.clone());
                             }
                         }
+                        downloaded_libraries.insert(name.replace("./", ""), local_path.clone());
                         if pending_downloads.load(Ordering::SeqCst) == 0 {
                             trace!("all downloads completed");
                             if last_completed_id == last_started_id && last_completed_id != last_triggered_id {
@@ -139,7 +193,9 @@ pub(crate) async fn remote_connection(
                                         }){
                                             info!("Triggering a Reload");
                                             last_triggered_id = last_completed_id;
-                                            let e = tx.send(DylibRunnerMessage::LoadRootLib { build_id: last_triggered_id, local_path, builder_type }).await;
+                                            load_started = download_started.take().map(|started| (last_triggered_id, started.elapsed(), Instant::now()));
+                                            let dependencies = load_order(root_lib_name.as_deref(), &changed_libraries, &downloaded_libraries);
+                                            let e = tx.send(DylibRunnerMessage::LoadRootLib { build_id: last_triggered_id, local_path, builder_type, dependencies }).await;
                                             trace!("Sent Reload Trigger: {e:?}");
                                         } else {
                                             trace!("local root doesn't exist yet - did download actually complete?");
@@ -169,7 +225,9 @@ pub(crate) async fn remote_connection(
                                     }){
                                         info!("Triggering a Reload");
                                         last_triggered_id = last_completed_id;
-                                        let e = tx.send(DylibRunnerMessage::LoadRootLib { build_id: last_triggered_id, local_path, builder_type }).await;
+                                        load_started = download_started.take().map(|started| (last_triggered_id, started.elapsed(), Instant::now()));
+                                        let dependencies = load_order(root_lib_name.as_deref(), &changed_libraries, &downloaded_libraries);
+                                        let e = tx.send(DylibRunnerMessage::LoadRootLib { build_id: last_triggered_id, local_path, builder_type, dependencies }).await;
                                         trace!("Sent Reload Trigger: {e:?}");
                                     } else {
                                         trace!("local root doesn't exist yet - did download actually complete?");
@@ -211,26 +269,28 @@ pub(crate) async fn remote_connection(
 
                                 builder_type = Some(bt);
                                 root_lib_name = initial_root_lib.as_ref().cloned();
+                                changed_libraries.clear();
+                                downloaded_libraries.clear();
+                                download_started = Some(Instant::now());
+                                known_libraries = libraries.iter().map(|(path, _)| path.as_str().replace("./", "")).collect();
                                 for (path, hash) in libraries {
-                                    download_file(&server, target, &library_path, path, hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
+                                    download_file(&server, &target, &library_path, path, hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
                                 }
                                 for (path, hash) in assets {
-                                    download_file(&server, target, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
+                                    download_file(&server, &target, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
                                 }
                                 last_started_id = most_recent_started_build;
                                 last_completed_id = most_recent_completed_build;
 
                             },
                             HotReloadMessage::UpdatedAssets(path, hash) => {
-                                download_file(&server, target, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
+                                download_file(&server, &target, &working_directory, path, hash, pending_downloads.clone(), download_tx.clone(), true, in_workspace);
                             },
-                            HotReloadMessage::BuildStarted(id) => {
-                                if id > last_started_id {
-                                    info!("build started: {id:?}");
-                                    last_started_id = id;
-                                }
+                            HotReloadMessage::BuildStarted(id) if id > last_started_id => {
+                                info!("build started: {id:?}");
+                                last_started_id = id;
                             },
-                            HotReloadMessage::BuildCompleted { id, libraries, root_library } => {
+                            HotReloadMessage::BuildCompleted { id, libraries, unchanged, root_library } => {
                                 info!("build completed: {id:?}");
                                 if id <= last_completed_id {
                                     continue;
@@ -238,9 +298,34 @@ pub(crate) async fn remote_connection(
                                 last_completed_id = id;
                                 root_lib_name = Some(root_library);
                                 root_lib_path = None;
+                                download_started = Some(Instant::now());
+                                trace!("{} libraries changed, {} unchanged", libraries.len(), unchanged.len());
+                                changed_libraries = libraries.iter().map(|(path, _, dependencies)| (path.replace("./", ""), dependencies.clone())).collect();
+                                downloaded_libraries.clear();
+                                for name in unchanged.iter().filter(|name| !known_libraries.contains(name.as_str())) {
+                                    // A zeroed hash never matches, so the library is downloaded
+                                    warn!("Build {id} expects {name} to be present already - downloading it");
+                                    download_file(&server, &target, &library_path, Utf8PathBuf::from(name), [0; 32], pending_downloads.clone(), download_tx.clone(), false, in_workspace);
+                                }
                                 for (path, hash, _) in &libraries {
-                                    download_file(&server,target,  &library_path, Utf8PathBuf::from(path), *hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
+                                    download_file(&server, &target,  &library_path, Utf8PathBuf::from(path), *hash, pending_downloads.clone(), download_tx.clone(), false, in_workspace);
+                                }
+                                known_libraries = libraries.into_iter().map(|(path, _, _)| path.replace("./", "")).chain(unchanged).collect();
+                            },
+                            HotReloadMessage::Diagnostics { id, diagnostics } => {
+                                for diagnostic in &diagnostics {
+                                    let text = diagnostic.rendered.as_ref().unwrap_or(&diagnostic.message);
+                                    match diagnostic.level {
+                                        DiagnosticLevel::Error => error!("build {id}: {text}"),
+                                        DiagnosticLevel::Warning => warn!("build {id}: {text}"),
+                                        _ => info!("build {id}: {text}"),
+                                    }
                                 }
+                                let _ = tx.send(DylibRunnerMessage::Diagnostics { build_id: id, diagnostics }).await;
+                            },
+                            HotReloadMessage::BuildProgress(progress) => {
+                                debug!("build {}: compiled {} ({}/{})", progress.build_id, progress.current_crate, progress.compiled, progress.estimated_total.map(|total| total.to_string()).unwrap_or_else(|| "?".to_string()));
+                                let _ = tx.send(DylibRunnerMessage::BuildProgress(progress)).await;
                             },
                             _ => {}
                         }
@@ -259,6 +344,88 @@ pub(crate) async fn remote_connection(
     }
 }
 
+/// Orders the changed libraries other than the root so that each comes after the libraries it depends on
+fn load_order(
+    root: Option<&str>,
+    changed: &HashMap<String, Vec<String>>,
+    downloaded: &HashMap<String, Utf8PathBuf>,
+) -> Vec<Utf8PathBuf> {
+    fn visit<'a>(
+        name: &'a str,
+        changed: &'a HashMap<String, Vec<String>>,
+        visited: &mut HashSet<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) {
+        if !visited.insert(name) {
+            return;
+        }
+        for dependency in changed.get(name).into_iter().flatten() {
+            if changed.contains_key(dependency) {
+                visit(dependency, changed, visited, order);
+            }
+        }
+        order.push(name);
+    }
+
+    let root = root.map(|root| root.replace("./", ""));
+    let mut names = changed.keys().collect::<Vec<_>>();
+    names.sort();
+
+    let mut visited = HashSet::new();
+    let mut order = vec![];
+    for name in names {
+        visit(name, changed, &mut visited, &mut order);
+    }
+
+    order
+        .into_iter()
+        .filter(|name| Some(*name) != root.as_deref())
+        .filter_map(|name| downloaded.get(name).cloned())
+        .collect()
+}
+
+async fn receive_handshake(
+    read: &mut (impl Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin),
+) -> Result<Vec<Capability>, DylibRunnerError> {
+    loop {
+        let Some(msg) = read.next().await else {
+            return Err(DylibRunnerError::HandshakeFailed(
+                "connection closed".to_string(),
+            ));
+        };
+        let binary = match msg? {
+            Message::Binary(binary) => binary,
+            Message::Ping(_) | Message::Pong(_) => continue,
+            other => {
+                return Err(DylibRunnerError::HandshakeFailed(format!(
+                    "unexpected message {other:?}"
+                )))
+            }
+        };
+        let response: ServerHandshake = rmp_serde::from_slice(&binary)
+            .map_err(|e| DylibRunnerError::HandshakeFailed(e.to_string()))?;
+        return match response {
+            ServerHandshake::Accepted {
+                protocol_version,
+                capabilities,
+            } => {
+                if (MIN_SUPPORTED_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+                    Ok(capabilities)
+                } else {
+                    Err(DylibRunnerError::IncompatibleServer(protocol_version))
+                }
+            }
+            ServerHandshake::Rejected {
+                server_protocol_version,
+                reason,
+            } => Err(DylibRunnerError::HandshakeRejected {
+                server_protocol_version,
+                reason,
+            }),
+        };
+    }
+}
+
 enum DownloadResult {
     Downloaded {
         name: String,
@@ -273,7 +440,7 @@ enum DownloadResult {
 #[allow(clippy::too_many_arguments)]
 fn download_file(
     server: &url::Url,
-    target: Target,
+    target: &Target,
     base_path: &Utf8Path,
     remote_path: Utf8PathBuf,
     hash: [u8; 32],
@@ -288,6 +455,7 @@ fn download_file(
             pending.fetch_add(1, Ordering::SeqCst);
         }
         let server = server.clone();
+        let target = target.clone();
         let base_path = base_path.to_owned();
         tokio::spawn(async move {
             let result =
@@ -421,3 +589,53 @@ async fn execute_download(
 
     Ok(local_path)
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use camino::Utf8PathBuf;
+
+    use super::load_order;
+
+    #[test]
+    fn loads_changed_libraries_after_their_dependencies() {
+        let changed = HashMap::from([
+            (
+                "libgame.5.so".to_string(),
+                vec![
+                    "libenemies.5.so".to_string(),
+                    "libphysics.5.so".to_string(),
+                    "libstd.so".to_string(),
+                ],
+            ),
+            (
+                "libenemies.5.so".to_string(),
+                vec!["libphysics.5.so".to_string(), "libstd.so".to_string()],
+            ),
+            ("libphysics.5.so".to_string(), vec!["libstd.so".to_string()]),
+        ]);
+        let downloaded = changed
+            .keys()
+            .map(|name| (name.clone(), Utf8PathBuf::from(format!("/libs/{name}"))))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(
+            load_order(Some("./libgame.5.so"), &changed, &downloaded),
+            vec![
+                Utf8PathBuf::from("/libs/libphysics.5.so"),
+                Utf8PathBuf::from("/libs/libenemies.5.so"),
+            ]
+        );
+
+        let mut cyclic = changed.clone();
+        cyclic.insert(
+            "libphysics.5.so".to_string(),
+            vec!["libenemies.5.so".to_string()],
+        );
+        assert_eq!(
+            load_order(Some("libgame.5.so"), &cyclic, &downloaded).len(),
+            2
+        );
+    }
+}
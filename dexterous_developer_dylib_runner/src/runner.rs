@@ -1,9 +1,9 @@
 use std::sync::Arc;
 
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 
 use dexterous_developer_instance::{runner::HotReloadInfoBuilder, UpdatedAsset};
-use dexterous_developer_types::cargo_path_utils::dylib_path;
+use dexterous_developer_types::{cargo_path_utils::dylib_path, BuilderTypes};
 use dylib_runner_message::DylibRunnerMessage;
 use error::DylibRunnerError;
 use ffi::{NEXT_LIBRARY, NEXT_UPDATE_VERSION, ORIGINAL_LIBRARY};
@@ -44,12 +44,13 @@ pub fn run_reloadable_app(
         return Err(DylibRunnerError::DylibPathsMissingLibraries);
     }
 
-    run_app(|tx, _| {
+    run_app(|tx, out_rx| {
         connect_to_server(
             working_directory,
             &library_path,
             server.clone(),
             tx,
+            out_rx,
             in_workspace,
         )
     })
@@ -91,7 +92,9 @@ pub fn run_app<
                     build_id,
                     local_path,
                     builder_type: bt,
+                    dependencies,
                 } => {
+                    load_dependencies(&dependencies, bt)?;
                     trace!("Loading Initial Root");
                     library = Some(LibraryHolder::new(&local_path, false, bt)?);
                     path = Some(local_path);
@@ -104,6 +107,12 @@ pub fn run_app<
                     continue;
                 }
                 DylibRunnerMessage::SerializedMessage { message: _ } => {}
+                DylibRunnerMessage::Diagnostics { build_id, .. } => {
+                    trace!("Diagnostics for build {build_id} before the initial root loaded");
+                }
+                DylibRunnerMessage::BuildProgress(progress) => {
+                    trace!("Progress before the initial root loaded - {progress:?}");
+                }
             }
         }
         trace!("Initial Root ID: {id:?}");
@@ -166,8 +175,13 @@ fn update_loop(
             DylibRunnerMessage::LoadRootLib {
                 build_id,
                 local_path,
-                ..
+                builder_type,
+                dependencies,
             } => {
+                if let Err(e) = load_dependencies(&dependencies, builder_type) {
+                    error!("Couldn't load the libraries build {build_id} depends on - {e}");
+                    continue;
+                }
                 trace!("Load Root New Library {local_path}");
                 NEXT_UPDATE_VERSION.store(build_id, std::sync::atomic::Ordering::SeqCst);
                 trace!("Stored Build ID: {build_id}");
@@ -209,6 +223,41 @@ fn update_loop(
                     );
                 }
             }
+            DylibRunnerMessage::Diagnostics {
+                build_id,
+                diagnostics,
+            } => {
+                if let Some(library) = ORIGINAL_LIBRARY.get() {
+                    trace!("Sending Diagnostics");
+                    let diagnostics = rmp_serde::to_vec(&(build_id, diagnostics))?;
+                    let _ = library.varied_call(
+                        "update_diagnostics_callback_internal",
+                        safer_ffi::Vec::from(diagnostics),
+                    );
+                }
+            }
+            DylibRunnerMessage::BuildProgress(progress) => {
+                if let Some(library) = ORIGINAL_LIBRARY.get() {
+                    trace!("Sending Build Progress");
+                    let progress = rmp_serde::to_vec(&progress)?;
+                    let _ = library.varied_call(
+                        "update_build_progress_callback_internal",
+                        safer_ffi::Vec::from(progress),
+                    );
+                }
+            }
         }
     }
 }
+
+/// Loads the libraries a new root library depends on, in order, so the root links against these versions
+fn load_dependencies(
+    dependencies: &[Utf8PathBuf],
+    builder_type: BuilderTypes,
+) -> Result<(), DylibRunnerError> {
+    for dependency in dependencies {
+        trace!("Loading Dependency {dependency}");
+        LibraryHolder::new(dependency, true, builder_type)?;
+    }
+    Ok(())
+}
@@ -34,7 +34,7 @@ pub struct CallResponse {
 pub mod internal {
     use camino::Utf8PathBuf;
     use chrono::{Local, Timelike};
-    use dexterous_developer_types::BuilderTypes;
+    use dexterous_developer_types::{BuildDiagnostic, BuildProgress, BuilderTypes};
     use once_cell::sync::OnceCell;
     use rmp_serde::encode::Error;
     use safer_ffi::ffi_export;
@@ -63,7 +63,7 @@ pub mod internal {
         use std::sync::{Arc, RwLock};
 
         use camino::Utf8PathBuf;
-        use dexterous_developer_types::BuilderTypes;
+        use dexterous_developer_types::{BuildDiagnostic, BuildProgress, BuilderTypes};
         use safer_ffi::ffi_export;
         use serde::de::DeserializeOwned;
         use tracing::error;
@@ -78,6 +78,12 @@ pub mod internal {
             RwLock::new(None);
         static MESSAGE_CALLBACK: RwLock<Option<Arc<dyn Fn(safer_ffi::Vec<u8>) + Send + Sync>>> =
             RwLock::new(None);
+        #[allow(clippy::type_complexity)]
+        static DIAGNOSTICS_CALLBACK: RwLock<
+            Option<Arc<dyn Fn(u32, Vec<BuildDiagnostic>) + Send + Sync>>,
+        > = RwLock::new(None);
+        static BUILD_PROGRESS_CALLBACK: RwLock<Option<Arc<dyn Fn(BuildProgress) + Send + Sync>>> =
+            RwLock::new(None);
 
         #[ffi_export]
         fn load_internal_library(path: safer_ffi::String) {
@@ -130,6 +136,44 @@ pub mod internal {
             }
         }
 
+        #[ffi_export]
+        fn update_diagnostics_callback_internal(diagnostics: safer_ffi::Vec<u8>) {
+            let Ok((build_id, diagnostics)) =
+                rmp_serde::from_slice::<(u32, Vec<BuildDiagnostic>)>(&diagnostics)
+            else {
+                error!("Couldn't parse build diagnostics");
+                return;
+            };
+
+            let current = DIAGNOSTICS_CALLBACK
+                .try_read()
+                .map_err(|e| HotReloadAccessError::AtomicError(format!("{e}")));
+
+            if let Ok(current) = current.as_ref() {
+                if let Some(current) = current.as_ref() {
+                    current(build_id, diagnostics);
+                }
+            }
+        }
+
+        #[ffi_export]
+        fn update_build_progress_callback_internal(progress: safer_ffi::Vec<u8>) {
+            let Ok(progress) = rmp_serde::from_slice::<BuildProgress>(&progress) else {
+                error!("Couldn't parse build progress");
+                return;
+            };
+
+            let current = BUILD_PROGRESS_CALLBACK
+                .try_read()
+                .map_err(|e| HotReloadAccessError::AtomicError(format!("{e}")));
+
+            if let Ok(current) = current.as_ref() {
+                if let Some(current) = current.as_ref() {
+                    current(progress);
+                }
+            }
+        }
+
         #[ffi_export]
         fn send_message_to_reloaded_app(message: safer_ffi::Vec<u8>) {
             let current = MESSAGE_CALLBACK
@@ -211,6 +255,34 @@ pub mod internal {
             *writer = Some(Arc::new(callback));
         }
 
+        pub(crate) fn diagnostics_callback(
+            callback: impl Fn(u32, Vec<BuildDiagnostic>) + Send + Sync + 'static,
+        ) {
+            let mut writer = match DIAGNOSTICS_CALLBACK.write() {
+                Ok(w) => w,
+                Err(e) => {
+                    error!("Failed To Set Diagnostics Callback {e}");
+                    return;
+                }
+            };
+
+            *writer = Some(Arc::new(callback));
+        }
+
+        pub(crate) fn build_progress_callback(
+            callback: impl Fn(BuildProgress) + Send + Sync + 'static,
+        ) {
+            let mut writer = match BUILD_PROGRESS_CALLBACK.write() {
+                Ok(w) => w,
+                Err(e) => {
+                    error!("Failed To Set Build Progress Callback {e}");
+                    return;
+                }
+            };
+
+            *writer = Some(Arc::new(callback));
+        }
+
         pub(crate) fn register_message_callback<T: DeserializeOwned>(
             callback: impl Fn(T) + Send + Sync + 'static,
         ) {
@@ -298,6 +370,25 @@ pub mod internal {
             dylib::update_asset_callback(callback);
         }
 
+        /// Called with the build id and the compiler's errors and warnings whenever the server
+        /// reports them - including for builds that fail, and so never get loaded
+        pub fn diagnostics_callback(
+            &mut self,
+            callback: impl Fn(u32, Vec<BuildDiagnostic>) + Send + Sync + 'static,
+        ) {
+            #[cfg(feature = "dylib")]
+            dylib::diagnostics_callback(callback);
+        }
+
+        /// Called whenever the server reports progress on a running build
+        pub fn build_progress_callback(
+            &mut self,
+            callback: impl Fn(BuildProgress) + Send + Sync + 'static,
+        ) {
+            #[cfg(feature = "dylib")]
+            dylib::build_progress_callback(callback);
+        }
+
         pub fn register_message_callback<T: DeserializeOwned>(
             &mut self,
             callback: impl Fn(T) + Send + Sync + 'static,
@@ -31,6 +31,7 @@ impl LibraryHolderInner {
 
         let default_library = match builder_type {
             BuilderTypes::Default => true,
+            BuilderTypes::Command => false,
         };
 
         let uuid = uuid::Uuid::new_v4();
@@ -1,3 +1,4 @@
 pub mod manager;
+pub mod metrics;
 pub mod server;
 pub use manager::{Manager, ManagerError};
@@ -4,8 +4,11 @@ use dexterous_developer_builder::types::{
     BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
     BuilderOutgoingMessages, CurrentBuildState, Watcher,
 };
-use dexterous_developer_types::Target;
-use std::{collections::HashSet, sync::Arc};
+use dexterous_developer_types::{PhaseTiming, Target};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use thiserror::Error;
 use tokio::{
     sync::broadcast::{self},
@@ -13,6 +16,8 @@ use tokio::{
 };
 use tracing::{error, info, trace};
 
+use crate::metrics::Metrics;
+
 #[derive(Clone)]
 
 pub struct Manager {
@@ -30,6 +35,9 @@ pub struct Manager {
     >,
     target_count: usize,
     watcher: Option<Arc<dyn Watcher>>,
+    /// The latest build each connected runner loaded - older builds are no longer in use
+    loaded_builds: Arc<DashMap<Target, HashMap<uuid::Uuid, u32>>>,
+    metrics: Metrics,
 }
 
 impl Default for Manager {
@@ -39,6 +47,8 @@ impl Default for Manager {
             targets: Default::default(),
             target_count: Default::default(),
             watcher: Default::default(),
+            loaded_builds: Default::default(),
+            metrics: Default::default(),
         }
     }
 }
@@ -63,6 +73,8 @@ impl Manager {
             targets: Default::default(),
             watcher: Some(watcher),
             target_count: 0,
+            loaded_builds: Default::default(),
+            metrics: Default::default(),
         }
     }
 
@@ -76,15 +88,23 @@ impl Manager {
     ) -> anyhow::Result<Self> {
         let builder = initializer.initialize_builder(self.watcher_channel.clone())?;
         let target = builder.target();
-        self.targets.entry(target).or_insert_with(|| {
+        self.targets.entry(target.clone()).or_insert_with(|| {
             self.target_count += 1;
-            let current_state = Arc::new(CurrentBuildState::new(builder.root_lib_name(), builder.builder_type()));
+            let current_state = Arc::new(match builder.last_completed_build() {
+                Some(build) => {
+                    info!("Serving build {} for {target:?} from a previous session", build.id);
+                    CurrentBuildState::from_completed_build(build, builder.builder_type())
+                }
+                None => CurrentBuildState::new(builder.root_lib_name(), builder.builder_type()),
+            });
             let (outgoing, output) = builder.outgoing_channel();
 
             let handle = {
                 let mut outgoing = outgoing.resubscribe();
                 let mut output = output.resubscribe();
                 let current_state = current_state.clone();
+                let metrics = self.metrics.clone();
+                let watcher = self.watcher.clone();
 
                 tokio::spawn(async move {
                     loop {
@@ -93,9 +113,19 @@ impl Manager {
                                 match msg {
                                     BuilderOutgoingMessages::Waiting => trace!("Builder for {target:?} is waiting"),
                                     BuilderOutgoingMessages::BuildStarted => trace!("Started building for {target:?}"),
+                                    BuilderOutgoingMessages::WatchCode(directories) => {
+                                        if let Some(watcher) = &watcher {
+                                            if let Err(e) = watcher.watch_code_directories(&directories) {
+                                                error!("Couldn't watch code for {target:?} - {e}");
+                                            }
+                                        }
+                                    }
                                 }
                             }
                             Ok(msg) = output.recv() => {
+                                if let BuildOutputMessages::Timings { timings, .. } = &msg {
+                                    metrics.record(&target, timings);
+                                }
                                 current_state.update(msg).await;
                             }
                             else => { break }
@@ -112,13 +142,17 @@ impl Manager {
             (outgoing, output, current_state, handle)
         });
 
-        let targets = self.targets.iter().map(|r| *r.key()).collect::<Vec<_>>();
+        let targets = self
+            .targets
+            .iter()
+            .map(|r| r.key().clone())
+            .collect::<Vec<_>>();
         info!("Able to build {targets:?}");
         Ok(self)
     }
 
     pub fn targets(&self) -> HashSet<Target> {
-        self.targets.iter().map(|key| *key.key()).collect()
+        self.targets.iter().map(|key| key.key().clone()).collect()
     }
 
     pub async fn watch_target(
@@ -128,7 +162,7 @@ impl Manager {
         let target_ref = self
             .targets
             .get(target)
-            .ok_or(ManagerError::MissingTarget(*target))?;
+            .ok_or_else(|| ManagerError::MissingTarget(target.clone()))?;
 
         let (_, output_rx, current_state, _) = target_ref.value();
 
@@ -136,10 +170,53 @@ impl Manager {
 
         let _ = self
             .watcher_channel
-            .send(BuilderIncomingMessages::RequestBuild(*target));
+            .send(BuilderIncomingMessages::RequestBuild(target.clone()));
         Ok(response)
     }
 
+    /// Records that a runner loaded a build, so the builder keeps its libraries around.
+    ///
+    /// This replaces the runner's previous build, letting the builder prune it.
+    pub fn runner_loaded_build(&self, target: &Target, runner: uuid::Uuid, build_id: u32) {
+        self.loaded_builds
+            .entry(target.clone())
+            .or_default()
+            .insert(runner, build_id);
+        self.send_loaded_builds(target);
+    }
+
+    /// Records how long a runner took to download and load a build
+    pub fn runner_reported_timings(&self, target: &Target, timings: &[PhaseTiming]) {
+        self.metrics.record(target, timings);
+    }
+
+    /// The build timings reported so far
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Forgets the builds a runner loaded, once it disconnects
+    pub fn runner_disconnected(&self, target: &Target, runner: uuid::Uuid) {
+        if let Some(mut runners) = self.loaded_builds.get_mut(target) {
+            runners.remove(&runner);
+        }
+        self.send_loaded_builds(target);
+    }
+
+    fn send_loaded_builds(&self, target: &Target) {
+        let build_ids = self
+            .loaded_builds
+            .get(target)
+            .map(|runners| runners.values().copied().collect())
+            .unwrap_or_default();
+        let _ = self
+            .watcher_channel
+            .send(BuilderIncomingMessages::LoadedBuilds {
+                target: target.clone(),
+                build_ids,
+            });
+    }
+
     pub fn get_filepath(
         &self,
         target: &Target,
@@ -148,7 +225,7 @@ impl Manager {
         let target_ref = self
             .targets
             .get(target)
-            .ok_or(ManagerError::MissingTarget(*target))?;
+            .ok_or_else(|| ManagerError::MissingTarget(target.clone()))?;
 
         let current_state = &target_ref.2;
 
@@ -170,7 +247,8 @@ mod tests {
 
     use super::*;
     use dexterous_developer_builder::types::{
-        Builder, BuilderIncomingMessages, BuilderOutgoingMessages, HashedFileRecord, WatcherError,
+        Builder, BuilderIncomingMessages, BuilderOutgoingMessages, CompletedBuild,
+        HashedFileRecord, WatcherError,
     };
 
     struct TestBuilderInitializer;
@@ -214,6 +292,10 @@ mod tests {
             vec![]
         }
 
+        fn last_completed_build(&self) -> Option<CompletedBuild> {
+            None
+        }
+
         fn builder_type(&self) -> dexterous_developer_types::BuilderTypes {
             dexterous_developer_types::BuilderTypes::Default
         }
@@ -259,6 +341,10 @@ mod tests {
             vec![]
         }
 
+        fn last_completed_build(&self) -> Option<CompletedBuild> {
+            None
+        }
+
         fn builder_type(&self) -> dexterous_developer_types::BuilderTypes {
             dexterous_developer_types::BuilderTypes::Default
         }
@@ -330,15 +416,14 @@ mod tests {
             let mut incoming_rx = incoming.subscribe();
             let (outgoing_tx, _) = tokio::sync::broadcast::channel(10);
             let (output_tx, _) = tokio::sync::broadcast::channel(10);
-            let _my_target = target;
-
             let handle = {
+                let target = target.clone();
                 let outgoing_tx = outgoing_tx.clone();
                 let output_tx = output_tx.clone();
                 tokio::spawn(async move {
                     while let Ok(recv) = incoming_rx.recv().await {
-                        if let BuilderIncomingMessages::RequestBuild(req) = recv {
-                            if req != target {
+                        if let BuilderIncomingMessages::RequestBuild(req) = &recv {
+                            if *req != target {
                                 continue;
                             }
                             if outgoing_tx
@@ -358,6 +443,7 @@ mod tests {
                                             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                                         ],
                                     )],
+                                    unchanged: vec![],
                                     id: 1,
                                     root_library: "root_lib".to_string(),
                                 })
@@ -366,7 +452,7 @@ mod tests {
                                 break;
                             }
                         }
-                        if let BuilderIncomingMessages::CodeChanged = recv {
+                        if let BuilderIncomingMessages::CodeChanged(_) = recv {
                             output_tx
                                 .send(BuildOutputMessages::EndedBuild {
                                     libraries: vec![HashedFileRecord::new(
@@ -378,6 +464,7 @@ mod tests {
                                             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                                         ],
                                     )],
+                                    unchanged: vec![],
                                     id: 1,
                                     root_library: "root_lib".to_string(),
                                 })
@@ -398,7 +485,7 @@ mod tests {
 
     impl Builder for TestChanneledBuilder {
         fn target(&self) -> Target {
-            self.target
+            self.target.clone()
         }
 
         fn outgoing_channel(
@@ -422,6 +509,10 @@ mod tests {
             vec![]
         }
 
+        fn last_completed_build(&self) -> Option<CompletedBuild> {
+            None
+        }
+
         fn builder_type(&self) -> dexterous_developer_types::BuilderTypes {
             dexterous_developer_types::BuilderTypes::Default
         }
@@ -439,7 +530,9 @@ mod tests {
         }
 
         async fn update(&self) {
-            let _ = self.channel.send(BuilderIncomingMessages::CodeChanged);
+            let _ = self
+                .channel
+                .send(BuilderIncomingMessages::CodeChanged(vec![]));
         }
     }
 
@@ -553,4 +646,33 @@ mod tests {
             assert!(hash != new_hash, "Original: {hash:?}, new: {new_hash:?}");
         }
     }
+
+    #[tokio::test]
+    async fn only_the_latest_build_each_runner_loaded_is_kept() {
+        let watcher = Arc::new(TestWatcher::new());
+        let mut loaded = watcher.channel.subscribe();
+        let manager = Manager::new(watcher.clone());
+        let runner = uuid::Uuid::new_v4();
+        let other_runner = uuid::Uuid::new_v4();
+
+        manager.runner_loaded_build(&Target::Android, other_runner, 1);
+        for build_id in 1..=5 {
+            manager.runner_loaded_build(&Target::Android, runner, build_id);
+        }
+
+        let mut latest = None;
+        while let Ok(message) = loaded.try_recv() {
+            if let BuilderIncomingMessages::LoadedBuilds { target, build_ids } = message {
+                assert_eq!(target, Target::Android);
+                latest = Some(build_ids);
+            }
+        }
+        assert_eq!(latest, Some(HashSet::from([1, 5])));
+
+        manager.runner_disconnected(&Target::Android, other_runner);
+        let Ok(BuilderIncomingMessages::LoadedBuilds { build_ids, .. }) = loaded.try_recv() else {
+            panic!("Expected the loaded builds after the runner disconnected");
+        };
+        assert_eq!(build_ids, HashSet::from([5]));
+    }
 }
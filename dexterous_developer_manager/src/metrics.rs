@@ -0,0 +1,154 @@
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use dexterous_developer_types::{BuildPhase, PhaseTiming, Target};
+
+/// Build phase timings reported by builders and runners, served on `/metrics`
+#[derive(Clone, Default)]
+pub struct Metrics {
+    phases: Arc<DashMap<(Target, BuildPhase), PhaseStats>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PhaseStats {
+    count: u64,
+    total: Duration,
+    last: Duration,
+    max: Duration,
+}
+
+impl Metrics {
+    /// Adds the timings from one build
+    pub fn record(&self, target: &Target, timings: &[PhaseTiming]) {
+        for timing in timings {
+            let mut stats = self
+                .phases
+                .entry((target.clone(), timing.phase))
+                .or_default();
+            stats.count += 1;
+            stats.total += timing.duration;
+            stats.last = timing.duration;
+            stats.max = stats.max.max(timing.duration);
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut phases = self
+            .phases
+            .iter()
+            .map(|entry| {
+                let (target, phase) = entry.key();
+                (target.to_string(), *phase, *entry.value())
+            })
+            .collect::<Vec<_>>();
+        phases.sort_by(|(a_target, a_phase, _), (b_target, b_phase, _)| {
+            a_target.cmp(b_target).then(a_phase.cmp(b_phase))
+        });
+
+        let mut output = String::new();
+        let _ = writeln!(
+            output,
+            "# HELP dexterous_developer_build_phase_seconds Time spent in each phase of a build"
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE dexterous_developer_build_phase_seconds summary"
+        );
+        for (target, phase, stats) in phases.iter() {
+            let _ = writeln!(
+                output,
+                "dexterous_developer_build_phase_seconds_sum{{target=\"{target}\",phase=\"{phase}\"}} {}",
+                stats.total.as_secs_f64()
+            );
+            let _ = writeln!(
+                output,
+                "dexterous_developer_build_phase_seconds_count{{target=\"{target}\",phase=\"{phase}\"}} {}",
+                stats.count
+            );
+        }
+
+        write_gauge(
+            &mut output,
+            "last",
+            "The duration of the most recent run of each phase",
+            &phases,
+            |stats| stats.last,
+        );
+        write_gauge(
+            &mut output,
+            "max",
+            "The longest run of each phase since the server started",
+            &phases,
+            |stats| stats.max,
+        );
+        output
+    }
+}
+
+fn write_gauge(
+    output: &mut String,
+    name: &str,
+    help: &str,
+    phases: &[(String, BuildPhase, PhaseStats)],
+    value: impl Fn(&PhaseStats) -> Duration,
+) {
+    let _ = writeln!(
+        output,
+        "# HELP dexterous_developer_build_phase_{name}_seconds {help}"
+    );
+    let _ = writeln!(
+        output,
+        "# TYPE dexterous_developer_build_phase_{name}_seconds gauge"
+    );
+    for (target, phase, stats) in phases {
+        let _ = writeln!(
+            output,
+            "dexterous_developer_build_phase_{name}_seconds{{target=\"{target}\",phase=\"{phase}\"}} {}",
+            value(stats).as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use dexterous_developer_types::{BuildPhase, PhaseTiming, Target};
+
+    use super::Metrics;
+
+    #[test]
+    fn renders_recorded_timings_per_target_and_phase() {
+        let metrics = Metrics::default();
+        metrics.record(
+            &Target::Linux,
+            &[
+                PhaseTiming::new(BuildPhase::Dependencies, Duration::from_secs(4)),
+                PhaseTiming::new(BuildPhase::ReloadedCrate, Duration::from_millis(500)),
+            ],
+        );
+        metrics.record(
+            &Target::Linux,
+            &[PhaseTiming::new(
+                BuildPhase::Dependencies,
+                Duration::from_secs(2),
+            )],
+        );
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains(
+            "dexterous_developer_build_phase_seconds_sum{target=\"x86_64-unknown-linux-gnu\",phase=\"dependencies\"} 6\n"
+        ));
+        assert!(rendered.contains(
+            "dexterous_developer_build_phase_seconds_count{target=\"x86_64-unknown-linux-gnu\",phase=\"dependencies\"} 2\n"
+        ));
+        assert!(rendered.contains(
+            "dexterous_developer_build_phase_last_seconds{target=\"x86_64-unknown-linux-gnu\",phase=\"dependencies\"} 2\n"
+        ));
+        assert!(rendered.contains(
+            "dexterous_developer_build_phase_max_seconds{target=\"x86_64-unknown-linux-gnu\",phase=\"reloaded_crate\"} 0.5\n"
+        ));
+    }
+}
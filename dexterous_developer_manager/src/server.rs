@@ -11,7 +11,7 @@ use axum::{
         ws::{self, WebSocket},
         Path, Request, State, WebSocketUpgrade,
     },
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Router,
@@ -20,8 +20,14 @@ use camino::Utf8PathBuf;
 use dexterous_developer_builder::types::{
     BuildOutputMessages, CurrentBuildState, HashedFileRecord,
 };
-use dexterous_developer_types::{HotReloadMessage, Target, TargetParseError};
-use futures_util::{SinkExt, StreamExt};
+use dexterous_developer_types::{
+    BuildDiagnostic, Capability, ClientHandshake, HotReloadMessage, RunnerMessage, ServerHandshake,
+    Target, TargetParseError, PROTOCOL_VERSION,
+};
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
 use thiserror::Error;
 use tokio::sync::broadcast;
 use tower::ServiceExt;
@@ -34,7 +40,8 @@ pub async fn run_server(port: u16, manager: Manager) -> Result<(), Error> {
     let app = Router::new()
         .route("/targets", get(list_targets))
         .route("/target/:target", get(connect_to_target))
-        .route("/files/:target/*file", get(target_file_loader));
+        .route("/files/:target/*file", get(target_file_loader))
+        .route("/metrics", get(metrics));
 
     let app = app.with_state(ServerState {
         manager: Arc::new(manager),
@@ -60,7 +67,8 @@ pub async fn run_test_server(
     let app = Router::new()
         .route("/targets", get(list_targets))
         .route("/target/:target", get(connect_to_target))
-        .route("/files/:target/*file", get(target_file_loader));
+        .route("/files/:target/*file", get(target_file_loader))
+        .route("/metrics", get(metrics));
 
     let app = app.with_state(ServerState {
         manager: Arc::new(manager),
@@ -112,6 +120,13 @@ async fn list_targets(state: State<ServerState>) -> Result<Vec<u8>, Error> {
     Ok(body_value)
 }
 
+async fn metrics(state: State<ServerState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.manager.metrics().render(),
+    )
+}
+
 async fn connect_to_target(
     target: Path<String>,
     ws: WebSocketUpgrade,
@@ -127,20 +142,30 @@ async fn connect_to_target(
             error!("Connection Error - {id} {target:?}: {e}");
             e
         })?;
+    let manager = state.manager.clone();
     Ok(ws.on_upgrade(move |socket| {
-        connected_to_target(id, socket, target, initial_build_state, builder_rx)
+        connected_to_target(id, socket, target, manager, initial_build_state, builder_rx)
     }))
 }
 
 async fn connected_to_target(
     id: uuid::Uuid,
     socket: WebSocket,
-    _target: Target,
+    target: Target,
+    manager: Arc<Manager>,
     initial_build_state: CurrentBuildState,
     mut builder_rx: broadcast::Receiver<BuildOutputMessages>,
 ) {
     info!("Client {id} Connected");
-    let (mut ws_sender, _) = socket.split();
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+
+    let Some(capabilities) = handshake(id, &mut ws_sender, &mut ws_receiver).await else {
+        let _ = ws_sender.close().await;
+        return;
+    };
+    info!("Client {id} completed the handshake with capabilities {capabilities:?}");
+    let send_diagnostics = capabilities.contains(&Capability::Diagnostics);
+    let send_progress = capabilities.contains(&Capability::BuildProgress);
 
     {
         let initial_state_message = HotReloadMessage::InitialState {
@@ -182,44 +207,144 @@ async fn connected_to_target(
         }
     }
 
-    while let Ok(msg) = tokio::select! {
+    loop {
+        let msg = tokio::select! {
         val = builder_rx.recv() => {
-            val.map(|msg| match &msg {
+            let Ok(msg) = val else {
+                break;
+            };
+            match &msg {
                 BuildOutputMessages::AssetUpdated(HashedFileRecord {  relative_path, hash, .. }) => Some(HotReloadMessage::UpdatedAssets(relative_path.clone(), *hash)),
                 BuildOutputMessages::KeepAlive => None,
                 BuildOutputMessages::StartedBuild(id) => Some(HotReloadMessage::BuildStarted(*id)),
-                BuildOutputMessages::EndedBuild { id, libraries, root_library } => Some(HotReloadMessage::BuildCompleted {
+                BuildOutputMessages::EndedBuild { id, libraries, unchanged, root_library } => Some(HotReloadMessage::BuildCompleted {
                     id: *id,
                     libraries: libraries.iter().map(|library| (library.name.clone(), library.hash, library.dependencies.clone())).collect(),
+                    unchanged: unchanged.clone(),
                     root_library: root_library.clone()
                 }),
                 BuildOutputMessages::FailedBuild(e) => {
                     error!("Failed Build - {e}");
+                    send_diagnostics.then(|| HotReloadMessage::Diagnostics {
+                        id: initial_build_state
+                            .most_recent_started_build
+                            .load(std::sync::atomic::Ordering::SeqCst),
+                        diagnostics: vec![BuildDiagnostic::error(e)],
+                    })
+                }
+                BuildOutputMessages::Diagnostics { id, diagnostics } => {
+                    send_diagnostics.then(|| HotReloadMessage::Diagnostics {
+                        id: *id,
+                        diagnostics: diagnostics.clone(),
+                    })
+                }
+                BuildOutputMessages::BuildProgress(progress) => {
+                    send_progress.then(|| HotReloadMessage::BuildProgress(progress.clone()))
+                }
+                BuildOutputMessages::CancelledBuild(id) => {
+                    info!("Cancelled Build - {id}");
+                    None
+                }
+                BuildOutputMessages::Timings { .. } => None,
+            }
+        }
+        incoming = ws_receiver.next() => {
+            match incoming {
+                Some(Ok(ws::Message::Binary(message))) => {
+                    match rmp_serde::from_slice::<RunnerMessage>(&message) {
+                        Ok(RunnerMessage::LoadedBuild(build_id)) => {
+                            trace!("Client {id} loaded build {build_id}");
+                            manager.runner_loaded_build(&target, id, build_id);
+                        }
+                        Ok(RunnerMessage::Timings { build_id, timings }) => {
+                            trace!("Client {id} reported timings for build {build_id} - {timings:?}");
+                            manager.runner_reported_timings(&target, &timings);
+                        }
+                        Err(e) => error!("Couldn't parse message from {id} - {e}"),
+                    }
                     None
                 }
-            })
+                Some(Ok(ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => None,
+            }
         }
-        _ = tokio::time::sleep(Duration::from_secs(5)) => Ok(Some(HotReloadMessage::KeepAlive))
-    } {
+        _ = tokio::time::sleep(Duration::from_secs(5)) => Some(HotReloadMessage::KeepAlive)
+        };
         let Some(msg) = msg else {
             continue;
         };
         let Ok(msg) = rmp_serde::to_vec(&msg) else {
             error!("Failed to serialize update for {id}");
             let _ = ws_sender.close().await;
-            return;
+            break;
         };
 
         if let Err(e) = ws_sender.send(ws::Message::Binary(msg)).await {
             error!("Failed to send update to {id} - {e}");
             let _ = ws_sender.close().await;
-            return;
+            break;
         }
     }
 
+    manager.runner_disconnected(&target, id);
     info!("Connection closed for {id}");
 }
 
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Waits for the client's [`ClientHandshake`], and replies with a [`ServerHandshake`].
+///
+/// Returns the negotiated capabilities, or `None` if the client is incompatible.
+async fn handshake(
+    id: uuid::Uuid,
+    ws_sender: &mut SplitSink<WebSocket, ws::Message>,
+    ws_receiver: &mut SplitStream<WebSocket>,
+) -> Option<Vec<Capability>> {
+    let rejected = |reason: String| ServerHandshake::Rejected {
+        server_protocol_version: PROTOCOL_VERSION,
+        reason,
+    };
+
+    let response = match tokio::time::timeout(HANDSHAKE_TIMEOUT, ws_receiver.next()).await {
+        Ok(Some(Ok(ws::Message::Binary(message)))) => {
+            match rmp_serde::from_slice::<ClientHandshake>(&message) {
+                Ok(client) => ServerHandshake::negotiate(&client),
+                Err(e) => rejected(format!("couldn't parse the client handshake - {e}")),
+            }
+        }
+        Ok(Some(Ok(_))) => rejected("expected a binary handshake message".to_string()),
+        Ok(Some(Err(e))) => {
+            error!("Client {id} failed before the handshake - {e}");
+            return None;
+        }
+        Ok(None) => {
+            error!("Client {id} disconnected before the handshake");
+            return None;
+        }
+        Err(_) => rejected(
+            "no handshake received - the runner is likely from an older release".to_string(),
+        ),
+    };
+
+    if let ServerHandshake::Rejected { reason, .. } = &response {
+        error!("Rejecting client {id} - {reason}");
+    }
+
+    let Ok(message) = rmp_serde::to_vec(&response) else {
+        error!("Failed to serialize handshake response for {id}");
+        return None;
+    };
+    if let Err(e) = ws_sender.send(ws::Message::Binary(message)).await {
+        error!("Failed to send handshake response to {id} - {e}");
+        return None;
+    }
+
+    match response {
+        ServerHandshake::Accepted { capabilities, .. } => Some(capabilities),
+        ServerHandshake::Rejected { .. } => None,
+    }
+}
+
 async fn target_file_loader(
     Path((target, file)): Path<(String, Utf8PathBuf)>,
     state: State<ServerState>,
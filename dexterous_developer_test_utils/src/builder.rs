@@ -3,7 +3,7 @@ use std::env::current_exe;
 use camino::Utf8PathBuf;
 use dexterous_developer_builder::types::{
     BuildOutputMessages, Builder, BuilderIncomingMessages, BuilderInitializer,
-    BuilderOutgoingMessages, HashedFileRecord,
+    BuilderOutgoingMessages, CompletedBuild, HashedFileRecord,
 };
 use dexterous_developer_types::Target;
 use tokio::sync::broadcast;
@@ -47,6 +47,7 @@ impl TestBuilderComms {
                     hash: Default::default(),
                     dependencies: vec![],
                 }],
+                unchanged: vec![],
                 root_library: example.clone(),
             })
             .unwrap();
@@ -78,7 +79,7 @@ impl TestBuilderInitializer {
 
         (
             Self {
-                target,
+                target: target.clone(),
                 outgoing: (outgoing_tx.clone(), output_tx.clone()),
                 root_lib_name,
             },
@@ -112,7 +113,7 @@ impl BuilderInitializer for TestBuilderInitializer {
 
 impl Builder for TestBuilder {
     fn target(&self) -> dexterous_developer_types::Target {
-        self.target
+        self.target.clone()
     }
 
     fn outgoing_channel(
@@ -138,6 +139,10 @@ impl Builder for TestBuilder {
         vec![]
     }
 
+    fn last_completed_build(&self) -> Option<CompletedBuild> {
+        None
+    }
+
     fn builder_type(&self) -> dexterous_developer_types::BuilderTypes {
         dexterous_developer_types::BuilderTypes::Default
     }
@@ -1,60 +1,166 @@
 use std::collections::HashMap;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::trace;
 
-use crate::{BuilderTypes, PackageOrExample, Target, TargetBuildSettings};
-use camino::Utf8PathBuf;
+use crate::{BuilderTypes, Linker, PackageOrExample, Target, TargetBuildSettings, KNOWN_TARGETS};
+use camino::{Utf8Path, Utf8PathBuf};
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub const CONFIG_FILE_NAME: &str = "Dexterous.toml";
+pub const LOCAL_CONFIG_FILE_NAME: &str = "Dexterous.local.toml";
+
+/// The contents of a `Dexterous.toml` file, or of `[package.metadata.dexterous]` / `[workspace.metadata.dexterous]`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct DexterousConfig {
+    /// Cargo features to enable for every build
     #[serde(default)]
     pub features: Vec<String>,
+    /// Folders containing assets that are sent to the running app when they change
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub asset_folders: Vec<camino::Utf8PathBuf>,
+    /// Folders containing code that triggers a rebuild when it changes
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub code_watch_folders: Vec<camino::Utf8PathBuf>,
+    /// Glob patterns for files in the code watch folders that shouldn't trigger a rebuild
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// The port the hot reload server listens on
     #[serde(default)]
     pub port: Option<u16>,
+    /// Settings for specific target triples - each key is a target triple to build for
     #[serde(default)]
     pub targets: HashMap<Target, ReloadTargetConfig>,
+    /// Settings for specific packages, by package name
     #[serde(default)]
     pub packages: HashMap<String, ReloadTargetConfig>,
+    /// Settings for specific examples, by example name
     #[serde(default)]
     pub examples: HashMap<String, ReloadTargetConfig>,
+    /// Settings used when no package or example is selected
     #[serde(default)]
     pub default_package: Option<ReloadTargetConfig>,
+    /// Named sets of settings, selected with `--profile`
+    #[serde(default)]
+    pub profiles: HashMap<String, ReloadTargetConfig>,
+    /// Environment variables set for the build
     #[serde(default)]
     pub environment: HashMap<String, String>,
+    /// A dotenv file loaded into the environment
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub env_file: Option<Utf8PathBuf>,
+    /// The `Cargo.toml` to build from
     #[serde(default)]
+    #[schemars(with = "Option<String>")]
     pub manifest_path: Option<Utf8PathBuf>,
+    /// Extra directories to search for dynamic libraries the build depends on
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub additional_library_directories: Vec<Utf8PathBuf>,
+    /// Apple SDK directories, used when cross compiling for apple targets
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub apple_sdk_directory: Vec<Utf8PathBuf>,
+    /// Build with the cranelift codegen backend
+    #[serde(default)]
+    pub cranelift: Option<bool>,
+    /// Stop a running build as soon as code changes, instead of waiting for it to finish
+    #[serde(default)]
+    pub cancel_stale_builds: Option<bool>,
+    /// The cargo profile to build with, such as `release` or a custom profile from `Cargo.toml` - defaults to `dev`
     #[serde(default)]
-    pub cranelift: bool,
+    pub profile: Option<String>,
+    /// How many of the most recent builds' libraries to keep on disk - defaults to 10.
+    /// Builds a runner still has loaded are always kept
+    #[serde(default)]
+    pub keep_builds: Option<u32>,
+    /// The linker to use - `lld`, `mold`, `system`, or `{ custom = "<path>" }`.
+    /// Defaults to lld on Linux and Windows, and falls back if the linker isn't installed
+    #[serde(default)]
+    pub linker: Option<Linker>,
+    /// The rustup toolchain to build with - a channel such as `nightly-2024-05-01`, or the path to a toolchain.
+    /// Defaults to the project's usual toolchain, or `nightly` when building with cranelift
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    /// Workspace crates to compile into their own reloadable libraries, so a change to one of them
+    /// only rebuilds and sends that crate and the root
+    #[serde(default)]
+    pub hot_crates: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+/// Settings for a specific target, package, example or profile
+#[derive(Serialize, Deserialize, Debug, Default, Clone, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ReloadTargetConfig {
+    /// Cargo features to enable
     #[serde(default)]
     pub features: Vec<String>,
+    /// Folders containing assets that are sent to the running app when they change
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub asset_folders: Vec<camino::Utf8PathBuf>,
+    /// Folders containing code that triggers a rebuild when it changes
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub code_watch_folders: Vec<camino::Utf8PathBuf>,
+    /// Glob patterns for files in the code watch folders that shouldn't trigger a rebuild
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Environment variables set for the build
     #[serde(default)]
     pub environment: HashMap<String, String>,
+    /// A dotenv file loaded into the environment
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub env_file: Option<Utf8PathBuf>,
+    /// The builder used for these builds
     #[serde(default)]
     pub builder: Option<BuilderTypes>,
+    /// The program and arguments the `Command` builder runs, such as `["make", "hot"]`
+    #[serde(default)]
+    pub build_command: Option<Vec<String>>,
+    /// The `Cargo.toml` to build from
     #[serde(default)]
+    #[schemars(with = "Option<String>")]
     pub manifest_path: Option<Utf8PathBuf>,
+    /// Extra directories to search for dynamic libraries the build depends on
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub additional_library_directories: Vec<Utf8PathBuf>,
+    /// Apple SDK directories, used when cross compiling for apple targets
     #[serde(default)]
+    #[schemars(with = "Vec<String>")]
     pub apple_sdk_directory: Vec<Utf8PathBuf>,
+    /// Build with the cranelift codegen backend
     #[serde(default)]
     pub cranelift: Option<bool>,
+    /// Stop a running build as soon as code changes, instead of waiting for it to finish
+    #[serde(default)]
+    pub cancel_stale_builds: Option<bool>,
+    /// The cargo profile to build with, such as `release` or a custom profile from `Cargo.toml` - defaults to `dev`
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// How many of the most recent builds' libraries to keep on disk - defaults to 10.
+    /// Builds a runner still has loaded are always kept
+    #[serde(default)]
+    pub keep_builds: Option<u32>,
+    /// The linker to use - `lld`, `mold`, `system`, or `{ custom = "<path>" }`.
+    /// Defaults to lld on Linux and Windows, and falls back if the linker isn't installed
+    #[serde(default)]
+    pub linker: Option<Linker>,
+    /// The rustup toolchain to build with - a channel such as `nightly-2024-05-01`, or the path to a toolchain.
+    /// Defaults to the project's usual toolchain, or `nightly` when building with cranelift
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    /// Workspace crates to compile into their own reloadable libraries, so a change to one of them
+    /// only rebuilds and sends that crate and the root
+    #[serde(default)]
+    pub hot_crates: Vec<String>,
 }
 
 impl DexterousConfig {
@@ -63,7 +169,7 @@ impl DexterousConfig {
         let path = if path.is_file() {
             path
         } else {
-            path.join("Dexterous.toml")
+            path.join(CONFIG_FILE_NAME)
         };
 
         if !path.exists() {
@@ -71,17 +177,564 @@ impl DexterousConfig {
             return Ok(Default::default());
         }
 
-        let file = tokio::fs::read_to_string(path).await?;
+        let file = tokio::fs::read_to_string(&path).await?;
 
-        let config = toml::from_str(&file)?;
+        let mut config: Self = toml::from_str(&file)?;
+        if let Some(directory) = path.parent() {
+            config.resolve_variables(directory, &process_variable)?;
+        }
 
         Ok(config)
     }
 
+    /// Parses a config as written - unlike [`DexterousConfig::load_toml`], the `env_file` isn't loaded
+    /// and `${VAR}` references are left as they are, since there's no directory to resolve them against
     pub fn load_toml_from_str(toml: &str) -> Result<Self, LoadConfigError> {
         let config = toml::from_str(toml)?;
         Ok(config)
     }
+
+    /// Parses a config like [`DexterousConfig::load_toml_from_str`], but rejects any keys that
+    /// aren't part of the config, along with the line each of them is on
+    pub fn load_toml_from_str_strict(toml: &str) -> Result<Self, LoadConfigError> {
+        let mut unknown = vec![];
+        let config: Self = serde_ignored::deserialize(toml::Deserializer::new(toml), |path| {
+            unknown.push(path_segments(&path))
+        })?;
+        if unknown.is_empty() {
+            return Ok(config);
+        }
+
+        let document = toml_edit::ImDocument::parse(toml).ok();
+        Err(LoadConfigError::UnknownKeys(
+            unknown
+                .into_iter()
+                .map(|path| UnknownKey {
+                    line: document
+                        .as_ref()
+                        .and_then(|document| key_line(toml, document.as_item(), &path)),
+                    path: join_segments(&path),
+                })
+                .collect(),
+        ))
+    }
+
+    /// Reads the `dexterous` table from a `[package.metadata]` or `[workspace.metadata]` value,
+    /// as provided by `cargo metadata`
+    pub fn from_cargo_metadata(
+        metadata: &serde_json::Value,
+    ) -> Result<Option<Self>, LoadConfigError> {
+        let Some(config) = metadata.get("dexterous") else {
+            return Ok(None);
+        };
+        let config = Self::deserialize(config)?;
+        Ok(Some(config))
+    }
+
+    /// Reads Cargo.toml metadata like [`DexterousConfig::from_cargo_metadata`], but rejects any
+    /// keys that aren't part of the config
+    pub fn from_cargo_metadata_strict(
+        metadata: &serde_json::Value,
+    ) -> Result<Option<Self>, LoadConfigError> {
+        let Some(config) = metadata.get("dexterous") else {
+            return Ok(None);
+        };
+        let mut unknown = vec![];
+        let config: Self = serde_ignored::deserialize(config, |path| {
+            unknown.push(UnknownKey {
+                path: format!("dexterous.{}", join_segments(&path_segments(&path))),
+                line: None,
+            })
+        })?;
+        if unknown.is_empty() {
+            Ok(Some(config))
+        } else {
+            Err(LoadConfigError::UnknownKeys(unknown))
+        }
+    }
+
+    /// A JSON Schema describing `Dexterous.toml`, for editors to validate against
+    pub fn json_schema() -> schemars::schema::RootSchema {
+        schemars::schema_for!(DexterousConfig)
+    }
+
+    /// Loads every config layer that exists for a package, and merges them.
+    ///
+    /// Equivalent to [`DexterousConfig::load_layered_with_metadata`] without any `Cargo.toml` metadata.
+    pub async fn load_layered(
+        workspace_root: &Utf8Path,
+        package_directory: Option<&Utf8Path>,
+    ) -> Result<Self, LoadConfigError> {
+        Self::load_layered_with_metadata(workspace_root, None, package_directory, None, false).await
+    }
+
+    /// Loads every config layer that exists for a package, and merges them.
+    ///
+    /// Layers are applied in this order, with later layers taking precedence:
+    /// 1. `[workspace.metadata.dexterous]` in the workspace `Cargo.toml`
+    /// 2. `Dexterous.toml` in the workspace root
+    /// 3. `[package.metadata.dexterous]` in the package `Cargo.toml`
+    /// 4. `Dexterous.toml` in the package directory
+    /// 5. `Dexterous.local.toml` in the workspace root
+    /// 6. `Dexterous.local.toml` in the package directory
+    ///
+    /// So within a directory, `Dexterous.toml` overrides the `Cargo.toml` metadata.
+    /// Relative paths within a layer are resolved against the directory containing that layer.
+    /// See [`DexterousConfig::merge`] for how the layers are combined.
+    ///
+    /// In strict mode, unknown keys in any of the files are reported as errors.
+    pub async fn load_layered_with_metadata(
+        workspace_root: &Utf8Path,
+        workspace_metadata: Option<Self>,
+        package_directory: Option<&Utf8Path>,
+        package_metadata: Option<Self>,
+        strict: bool,
+    ) -> Result<Self, LoadConfigError> {
+        let workspace_root = workspace_root.canonicalize_utf8()?;
+        let package_directory = package_directory
+            .map(|dir| dir.canonicalize_utf8())
+            .transpose()?;
+
+        let mut directories = vec![(
+            workspace_root.clone(),
+            workspace_metadata.into_iter().collect::<Vec<_>>(),
+        )];
+        match package_directory {
+            Some(package_directory) if package_directory != workspace_root => {
+                directories.push((package_directory, package_metadata.into_iter().collect()));
+            }
+            _ => directories[0].1.extend(package_metadata),
+        }
+
+        let mut config = Self::default();
+
+        for (directory, metadata_layers) in directories.iter_mut() {
+            for mut layer in metadata_layers.drain(..) {
+                trace!("Loading config layer from Cargo.toml metadata in {directory}");
+                layer.resolve_variables(directory, &process_variable)?;
+                layer.rebase_paths(directory);
+                config = config.merge(layer);
+            }
+            if let Some(layer) = Self::load_layer(directory, CONFIG_FILE_NAME, strict).await? {
+                config = config.merge(layer);
+            }
+        }
+
+        for (directory, _) in directories.iter() {
+            if let Some(layer) = Self::load_layer(directory, LOCAL_CONFIG_FILE_NAME, strict).await?
+            {
+                config = config.merge(layer);
+            }
+        }
+
+        Ok(config)
+    }
+
+    async fn load_layer(
+        directory: &Utf8Path,
+        file_name: &str,
+        strict: bool,
+    ) -> Result<Option<Self>, LoadConfigError> {
+        let path = directory.join(file_name);
+        if !path.exists() {
+            trace!("No config layer at {path}");
+            return Ok(None);
+        }
+        trace!("Loading config layer from {path}");
+        let file = tokio::fs::read_to_string(&path).await?;
+        let mut layer = if strict {
+            Self::load_toml_from_str_strict(&file).map_err(|e| match e {
+                LoadConfigError::UnknownKeys(keys) => LoadConfigError::UnknownKeysInFile {
+                    path: path.clone(),
+                    keys,
+                },
+                e => e,
+            })?
+        } else {
+            Self::load_toml_from_str(&file)?
+        };
+        layer.resolve_variables(directory, &process_variable)?;
+        layer.rebase_paths(directory);
+        Ok(Some(layer))
+    }
+
+    /// Merges an overlay on top of this config.
+    ///
+    /// Single values (such as `port` or `cranelift`) are replaced when the overlay sets them,
+    /// environment variables are replaced key by key, lists are appended to, and
+    /// target, package and example sections are merged with the same rules.
+    pub fn merge(mut self, overlay: Self) -> Self {
+        extend_unique(&mut self.features, overlay.features);
+        extend_unique(&mut self.asset_folders, overlay.asset_folders);
+        extend_unique(&mut self.code_watch_folders, overlay.code_watch_folders);
+        extend_unique(&mut self.ignore, overlay.ignore);
+        self.port = overlay.port.or(self.port);
+        merge_sections(&mut self.targets, overlay.targets);
+        merge_sections(&mut self.packages, overlay.packages);
+        merge_sections(&mut self.examples, overlay.examples);
+        merge_sections(&mut self.profiles, overlay.profiles);
+        self.default_package = match (self.default_package, overlay.default_package) {
+            (Some(base), Some(overlay)) => Some(base.merge(overlay)),
+            (base, overlay) => overlay.or(base),
+        };
+        self.environment.extend(overlay.environment);
+        self.env_file = overlay.env_file.or(self.env_file);
+        self.manifest_path = overlay.manifest_path.or(self.manifest_path);
+        extend_unique(
+            &mut self.additional_library_directories,
+            overlay.additional_library_directories,
+        );
+        extend_unique(&mut self.apple_sdk_directory, overlay.apple_sdk_directory);
+        self.cranelift = overlay.cranelift.or(self.cranelift);
+        self.cancel_stale_builds = overlay.cancel_stale_builds.or(self.cancel_stale_builds);
+        self.profile = overlay.profile.or(self.profile);
+        self.keep_builds = overlay.keep_builds.or(self.keep_builds);
+        self.linker = overlay.linker.or(self.linker);
+        self.toolchain = overlay.toolchain.or(self.toolchain);
+        extend_unique(&mut self.hot_crates, overlay.hot_crates);
+        self
+    }
+
+    /// Loads the `env_file` into the environment, and expands `${VAR}` and `${VAR:-default}`
+    /// in the environment, `asset_folders` and `additional_library_directories`.
+    ///
+    /// Variables are looked up in `lookup` first, then in the `env_file`. Values set directly in
+    /// `environment` take precedence over the ones loaded from the `env_file`.
+    /// Relative `env_file` paths are resolved against `directory`.
+    fn resolve_variables(
+        &mut self,
+        directory: &Utf8Path,
+        lookup: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(), LoadConfigError> {
+        let file_variables = load_env_file(self.env_file.as_ref(), directory)?;
+        resolve_fields(
+            &mut self.environment,
+            &mut self.asset_folders,
+            &mut self.additional_library_directories,
+            file_variables.clone(),
+            &file_variables,
+            lookup,
+        )?;
+        for section in self
+            .targets
+            .values_mut()
+            .chain(self.packages.values_mut())
+            .chain(self.examples.values_mut())
+            .chain(self.profiles.values_mut())
+            .chain(self.default_package.iter_mut())
+        {
+            section.resolve_variables(directory, &file_variables, lookup)?;
+        }
+        Ok(())
+    }
+
+    fn rebase_paths(&mut self, base: &Utf8Path) {
+        rebase_all(&mut self.asset_folders, base);
+        rebase_all(&mut self.code_watch_folders, base);
+        rebase_all(&mut self.additional_library_directories, base);
+        rebase_all(&mut self.apple_sdk_directory, base);
+        if let Some(manifest_path) = self.manifest_path.as_mut() {
+            rebase(manifest_path, base);
+        }
+        if let Some(env_file) = self.env_file.as_mut() {
+            rebase(env_file, base);
+        }
+        for section in self
+            .targets
+            .values_mut()
+            .chain(self.packages.values_mut())
+            .chain(self.examples.values_mut())
+            .chain(self.profiles.values_mut())
+            .chain(self.default_package.iter_mut())
+        {
+            section.rebase_paths(base);
+        }
+    }
+}
+
+impl ReloadTargetConfig {
+    /// Merges an overlay on top of this section, following the same rules as [`DexterousConfig::merge`]
+    pub fn merge(mut self, overlay: Self) -> Self {
+        extend_unique(&mut self.features, overlay.features);
+        extend_unique(&mut self.asset_folders, overlay.asset_folders);
+        extend_unique(&mut self.code_watch_folders, overlay.code_watch_folders);
+        extend_unique(&mut self.ignore, overlay.ignore);
+        self.environment.extend(overlay.environment);
+        self.env_file = overlay.env_file.or(self.env_file);
+        self.builder = overlay.builder.or(self.builder);
+        self.build_command = overlay.build_command.or(self.build_command);
+        self.manifest_path = overlay.manifest_path.or(self.manifest_path);
+        extend_unique(
+            &mut self.additional_library_directories,
+            overlay.additional_library_directories,
+        );
+        extend_unique(&mut self.apple_sdk_directory, overlay.apple_sdk_directory);
+        self.cranelift = overlay.cranelift.or(self.cranelift);
+        self.cancel_stale_builds = overlay.cancel_stale_builds.or(self.cancel_stale_builds);
+        self.profile = overlay.profile.or(self.profile);
+        self.keep_builds = overlay.keep_builds.or(self.keep_builds);
+        self.linker = overlay.linker.or(self.linker);
+        self.toolchain = overlay.toolchain.or(self.toolchain);
+        extend_unique(&mut self.hot_crates, overlay.hot_crates);
+        self
+    }
+
+    /// Resolves variables like [`DexterousConfig::resolve_variables`], with the section's own
+    /// `env_file` taking precedence over the one loaded for the whole layer.
+    /// Only the section's own `env_file` is added to its environment.
+    fn resolve_variables(
+        &mut self,
+        directory: &Utf8Path,
+        layer_variables: &HashMap<String, String>,
+        lookup: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(), LoadConfigError> {
+        let own_variables = load_env_file(self.env_file.as_ref(), directory)?;
+        let mut file_variables = layer_variables.clone();
+        file_variables.extend(own_variables.clone());
+        resolve_fields(
+            &mut self.environment,
+            &mut self.asset_folders,
+            &mut self.additional_library_directories,
+            own_variables,
+            &file_variables,
+            lookup,
+        )
+    }
+
+    fn rebase_paths(&mut self, base: &Utf8Path) {
+        rebase_all(&mut self.asset_folders, base);
+        rebase_all(&mut self.code_watch_folders, base);
+        rebase_all(&mut self.additional_library_directories, base);
+        rebase_all(&mut self.apple_sdk_directory, base);
+        if let Some(manifest_path) = self.manifest_path.as_mut() {
+            rebase(manifest_path, base);
+        }
+        if let Some(env_file) = self.env_file.as_mut() {
+            rebase(env_file, base);
+        }
+    }
+}
+
+fn process_variable(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn load_env_file(
+    env_file: Option<&Utf8PathBuf>,
+    directory: &Utf8Path,
+) -> Result<HashMap<String, String>, LoadConfigError> {
+    let Some(env_file) = env_file else {
+        return Ok(HashMap::new());
+    };
+    let mut path = env_file.clone();
+    rebase(&mut path, directory);
+    trace!("Loading environment file {path}");
+
+    let iter = dotenvy::from_path_iter(&path).map_err(|source| LoadConfigError::EnvFileError {
+        path: path.clone(),
+        source,
+    })?;
+    iter.map(|entry| {
+        entry.map_err(|source| LoadConfigError::EnvFileError {
+            path: path.clone(),
+            source,
+        })
+    })
+    .collect()
+}
+
+fn resolve_fields(
+    environment: &mut HashMap<String, String>,
+    asset_folders: &mut [Utf8PathBuf],
+    additional_library_directories: &mut [Utf8PathBuf],
+    mut resolved: HashMap<String, String>,
+    file_variables: &HashMap<String, String>,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), LoadConfigError> {
+    let lookup = |name: &str| lookup(name).or_else(|| file_variables.get(name).cloned());
+
+    for (key, value) in environment.drain() {
+        resolved.insert(key, interpolate(&value, &lookup)?);
+    }
+    *environment = resolved;
+
+    for path in asset_folders
+        .iter_mut()
+        .chain(additional_library_directories.iter_mut())
+    {
+        *path = Utf8PathBuf::from(interpolate(path.as_str(), &lookup)?);
+    }
+    Ok(())
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` references in a value. `$$` produces a literal `$`.
+///
+/// As in POSIX shells, the default is used when the variable is unset or empty.
+fn interpolate(
+    value: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, LoadConfigError> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        if let Some(after) = after.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(after) = after.strip_prefix('{') else {
+            result.push('$');
+            rest = after;
+            continue;
+        };
+        let Some(end) = after.find('}') else {
+            return Err(LoadConfigError::UnterminatedVariable(value.to_string()));
+        };
+
+        let reference = &after[..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        let value_or_default = match (lookup(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => Some(default.to_string()),
+            (None, default) => default.map(str::to_string),
+            (value, _) => value,
+        };
+        match value_or_default {
+            Some(replacement) => result.push_str(&replacement),
+            None => {
+                return Err(LoadConfigError::MissingVariable {
+                    variable: name.to_string(),
+                    value: value.to_string(),
+                })
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// A key that isn't part of the config, found while parsing in strict mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub path: String,
+    pub line: Option<usize>,
+}
+
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: unknown key `{}`", self.path),
+            None => write!(f, "unknown key `{}`", self.path),
+        }
+    }
+}
+
+fn format_unknown_keys(keys: &[UnknownKey]) -> String {
+    keys.iter()
+        .map(|key| format!("  {key}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+fn path_segments(path: &serde_ignored::Path) -> Vec<PathSegment> {
+    match path {
+        serde_ignored::Path::Root => vec![],
+        serde_ignored::Path::Seq { parent, index } => {
+            let mut segments = path_segments(parent);
+            segments.push(PathSegment::Index(*index));
+            segments
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            let mut segments = path_segments(parent);
+            segments.push(PathSegment::Key(key.clone()));
+            segments
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => path_segments(parent),
+    }
+}
+
+fn join_segments(path: &[PathSegment]) -> String {
+    let mut result = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) => {
+                if !result.is_empty() {
+                    result.push('.');
+                }
+                result.push_str(key);
+            }
+            PathSegment::Index(index) => result.push_str(&format!("[{index}]")),
+        }
+    }
+    result
+}
+
+/// Finds the line (starting from 1) that the last key in a path is defined on
+fn key_line(source: &str, root: &toml_edit::Item, path: &[PathSegment]) -> Option<usize> {
+    let mut item = root;
+    let mut span = None;
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) => {
+                let (key, value) = item.as_table_like()?.get_key_value(key)?;
+                span = key.span();
+                item = value;
+            }
+            PathSegment::Index(index) => {
+                item = item.get(*index)?;
+            }
+        }
+    }
+    let span = span?;
+    Some(source.get(..span.start)?.matches('\n').count() + 1)
+}
+
+fn extend_unique<T: PartialEq>(base: &mut Vec<T>, overlay: Vec<T>) {
+    for value in overlay {
+        if !base.contains(&value) {
+            base.push(value);
+        }
+    }
+}
+
+fn merge_sections<K: std::hash::Hash + Eq>(
+    base: &mut HashMap<K, ReloadTargetConfig>,
+    overlay: HashMap<K, ReloadTargetConfig>,
+) {
+    for (key, section) in overlay {
+        let merged = match base.remove(&key) {
+            Some(existing) => existing.merge(section),
+            None => section,
+        };
+        base.insert(key, merged);
+    }
+}
+
+fn rebase(path: &mut Utf8PathBuf, base: &Utf8Path) {
+    if path.is_relative() {
+        let relative = path.strip_prefix(".").unwrap_or(path);
+        *path = base.join(relative);
+    }
+}
+
+fn rebase_all(paths: &mut [Utf8PathBuf], base: &Utf8Path) {
+    for path in paths.iter_mut() {
+        rebase(path, base);
+    }
 }
 
 #[derive(Error, Debug)]
@@ -90,6 +743,40 @@ pub enum LoadConfigError {
     IoError(#[from] std::io::Error),
     #[error("Couldn't parse config file {0}")]
     ParseError(#[from] toml::de::Error),
+    #[error("Couldn't parse dexterous config in Cargo.toml metadata {0}")]
+    MetadataParseError(#[from] serde_json::Error),
+    #[error("Couldn't load environment file {path} - {source}")]
+    EnvFileError {
+        path: Utf8PathBuf,
+        source: dotenvy::Error,
+    },
+    #[error("Environment variable {variable} is not set and has no default, in \"{value}\"")]
+    MissingVariable { variable: String, value: String },
+    #[error("Unterminated variable reference in \"{0}\"")]
+    UnterminatedVariable(String),
+    #[error("Unknown keys in config:\n{}", format_unknown_keys(.0))]
+    UnknownKeys(Vec<UnknownKey>),
+    #[error("Unknown keys in {path}:\n{}", format_unknown_keys(.keys))]
+    UnknownKeysInFile {
+        path: Utf8PathBuf,
+        keys: Vec<UnknownKey>,
+    },
+}
+
+impl JsonSchema for Target {
+    fn schema_name() -> String {
+        "Target".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = String::json_schema(gen).into_object();
+        schema.metadata().description = Some("A rust target triple".to_string());
+        schema.metadata().examples = KNOWN_TARGETS
+            .iter()
+            .map(|target| serde_json::Value::String(target.to_string()))
+            .collect();
+        schema.into()
+    }
 }
 
 impl DexterousConfig {
@@ -97,9 +784,36 @@ impl DexterousConfig {
         &self,
         package_or_example: Option<PackageOrExample>,
         features: &[String],
+        profile: Option<&str>,
     ) -> Result<Vec<(Target, TargetBuildSettings)>, BuildSettingsGenerationError> {
+        Ok(self
+            .explain_build_settings(package_or_example, features, profile)?
+            .into_iter()
+            .map(|explained| (explained.target, explained.settings))
+            .collect())
+    }
+
+    /// Generates the build settings for each target, along with the layer that supplied
+    /// each field and warnings for any settings that end up having no effect.
+    ///
+    /// If a profile is selected, it is applied on top of the rest of the config - its features,
+    /// folders and environment variables are added, and its single values take precedence.
+    pub fn explain_build_settings(
+        &self,
+        package_or_example: Option<PackageOrExample>,
+        features: &[String],
+        profile: Option<&str>,
+    ) -> Result<Vec<ExplainedBuildSettings>, BuildSettingsGenerationError> {
         let package_or_example = package_or_example.unwrap_or_default();
 
+        let profile_config =
+            match profile {
+                Some(profile) => self.profiles.get(profile).cloned().ok_or_else(|| {
+                    BuildSettingsGenerationError::UnknownProfile(profile.to_string())
+                })?,
+                None => ReloadTargetConfig::default(),
+            };
+
         let package_specific_config = match &package_or_example {
             PackageOrExample::DefaulPackage => {
                 self.default_package.as_ref().cloned().unwrap_or_default()
@@ -112,152 +826,988 @@ impl DexterousConfig {
             }
         };
 
-        let global_builder = package_specific_config.builder;
-
-        let global_manifest = package_specific_config
-            .manifest_path
-            .as_ref()
-            .or(self.manifest_path.as_ref());
-
-        let global_features = features
-            .iter()
-            .chain(package_specific_config.features.iter())
-            .chain(self.features.iter())
-            .map(|s| s.as_str())
-            .collect::<Vec<_>>();
-        let global_asset_folders = package_specific_config
-            .asset_folders
-            .iter()
-            .chain(self.asset_folders.iter())
-            .map(|s| s.as_str())
-            .collect::<Vec<_>>();
-        let global_environment_variables = package_specific_config
-            .environment
-            .iter()
-            .chain(self.environment.iter())
-            .map(|(key, value)| (key.to_owned(), value.to_owned()))
-            .collect::<HashMap<_, _>>();
-        let global_library_directories = package_specific_config
-            .additional_library_directories
-            .iter()
-            .chain(self.additional_library_directories.iter())
-            .cloned()
-            .collect::<Vec<_>>();
-        let global_apple_sdk = package_specific_config
-            .apple_sdk_directory
-            .iter()
-            .chain(self.apple_sdk_directory.iter())
-            .cloned()
-            .collect::<Vec<_>>();
-        let global_cranelift = match package_specific_config.cranelift {
-            Some(v) => Some(v),
-            None => Some(self.cranelift),
-        };
-
         let mut targets = self
             .targets
             .iter()
-            .map(|(target, settings)| {
-                (
-                    *target,
-                    settings.features.clone(),
-                    settings.asset_folders.clone(),
-                    settings.environment.clone(),
-                    settings.builder,
-                    settings.manifest_path.clone(),
-                    settings.additional_library_directories.clone(),
-                    settings.apple_sdk_directory.clone(),
-                    settings.cranelift,
-                )
-            })
+            .map(|(target, settings)| (target.clone(), settings.clone()))
             .collect::<Vec<_>>();
 
         if targets.is_empty() {
             let default_target =
                 Target::current().ok_or(BuildSettingsGenerationError::NoDefaultTarget)?;
-            targets.push((
-                default_target,
-                vec![],
-                vec![],
-                HashMap::new(),
-                None,
-                None,
-                vec![],
-                vec![],
-                None,
-            ))
+            targets.push((default_target, ReloadTargetConfig::default()));
         }
 
         Ok(targets
             .into_iter()
-            .map(
-                move |(
-                    target,
-                    mut features,
-                    mut asset_folders,
-                    mut environment,
-                    builder,
-                    manifest_path,
-                    mut additional_library_directories,
-                    mut apple_sdk_directory,
-                    cranelift,
-                )| {
-                    for f in global_features.iter() {
-                        features.push(f.to_string());
+            .map(|(target, target_config)| {
+                let mut explained = ExplainedBuildSettings::new(target.clone());
+
+                let features = explained.collect_list(
+                    "features",
+                    [
+                        (SettingSource::Target, &target_config.features),
+                        (SettingSource::CommandLine, &features.to_vec()),
+                        (SettingSource::Profile, &profile_config.features),
+                        (SettingSource::Package, &package_specific_config.features),
+                        (SettingSource::Global, &self.features),
+                    ],
+                );
+                let asset_folders = explained.collect_list(
+                    "asset_folders",
+                    [
+                        (SettingSource::Target, &target_config.asset_folders),
+                        (SettingSource::Profile, &profile_config.asset_folders),
+                        (SettingSource::Package, &package_specific_config.asset_folders),
+                        (SettingSource::Global, &self.asset_folders),
+                    ],
+                );
+                let code_watch_folders = explained.collect_list(
+                    "code_watch_folders",
+                    [
+                        (SettingSource::Target, &target_config.code_watch_folders),
+                        (SettingSource::Profile, &profile_config.code_watch_folders),
+                        (
+                            SettingSource::Package,
+                            &package_specific_config.code_watch_folders,
+                        ),
+                        (SettingSource::Global, &self.code_watch_folders),
+                    ],
+                );
+                let ignore = explained.collect_list(
+                    "ignore",
+                    [
+                        (SettingSource::Target, &target_config.ignore),
+                        (SettingSource::Profile, &profile_config.ignore),
+                        (SettingSource::Package, &package_specific_config.ignore),
+                        (SettingSource::Global, &self.ignore),
+                    ],
+                );
+                let hot_crates = explained.collect_list(
+                    "hot_crates",
+                    [
+                        (SettingSource::Target, &target_config.hot_crates),
+                        (SettingSource::Profile, &profile_config.hot_crates),
+                        (SettingSource::Package, &package_specific_config.hot_crates),
+                        (SettingSource::Global, &self.hot_crates),
+                    ],
+                );
+                let additional_library_directories = explained.collect_list(
+                    "additional_library_directories",
+                    [
+                        (
+                            SettingSource::Target,
+                            &target_config.additional_library_directories,
+                        ),
+                        (
+                            SettingSource::Profile,
+                            &profile_config.additional_library_directories,
+                        ),
+                        (
+                            SettingSource::Package,
+                            &package_specific_config.additional_library_directories,
+                        ),
+                        (SettingSource::Global, &self.additional_library_directories),
+                    ],
+                );
+                let apple_sdk_directory = explained.collect_list(
+                    "apple_sdk_directory",
+                    [
+                        (SettingSource::Target, &target_config.apple_sdk_directory),
+                        (SettingSource::Profile, &profile_config.apple_sdk_directory),
+                        (
+                            SettingSource::Package,
+                            &package_specific_config.apple_sdk_directory,
+                        ),
+                        (SettingSource::Global, &self.apple_sdk_directory),
+                    ],
+                );
+                if !apple_sdk_directory.is_empty()
+                    && !target.is_apple()
+                {
+                    explained.warn(format!(
+                        "apple_sdk_directory has no effect when building for {target}"
+                    ));
+                }
+
+                let environment = explained.collect_environment([
+                    (SettingSource::Target, &target_config.environment),
+                    (SettingSource::Package, &package_specific_config.environment),
+                    (SettingSource::Global, &self.environment),
+                    (SettingSource::Profile, &profile_config.environment),
+                ]);
+
+                let builder = explained.pick_first(
+                    "builder",
+                    [
+                        (SettingSource::Profile, profile_config.builder),
+                        (SettingSource::Package, package_specific_config.builder),
+                        (SettingSource::Target, target_config.builder),
+                    ],
+                    true,
+                );
+                let builder = builder.unwrap_or_default();
+
+                let build_command = explained
+                    .pick_first(
+                        "build_command",
+                        [
+                            (SettingSource::Profile, profile_config.build_command.clone()),
+                            (
+                                SettingSource::Package,
+                                package_specific_config.build_command.clone(),
+                            ),
+                            (SettingSource::Target, target_config.build_command.clone()),
+                        ],
+                        false,
+                    )
+                    .unwrap_or_default();
+
+                let manifest_path = explained.pick_first(
+                    "manifest_path",
+                    [
+                        (SettingSource::Profile, profile_config.manifest_path.clone()),
+                        (SettingSource::Target, target_config.manifest_path.clone()),
+                        (
+                            SettingSource::Package,
+                            package_specific_config.manifest_path.clone(),
+                        ),
+                        (SettingSource::Global, self.manifest_path.clone()),
+                    ],
+                    false,
+                );
+
+                let (global_cranelift_source, global_cranelift) =
+                    match package_specific_config.cranelift {
+                        Some(v) => (SettingSource::Package, Some(v)),
+                        None => (SettingSource::Global, self.cranelift),
+                    };
+                let craneflift = match (
+                    profile_config.cranelift,
+                    target_config.cranelift,
+                    global_cranelift,
+                ) {
+                    (Some(value), _, _) => {
+                        explained.record("cranelift", SettingSource::Profile);
+                        value
+                    }
+                    (None, Some(true), _) => {
+                        explained.record("cranelift", SettingSource::Target);
+                        true
                     }
-                    for a in global_asset_folders.iter() {
-                        asset_folders.push(Utf8PathBuf::from(*a));
+                    (None, Some(false), Some(true)) => {
+                        explained.warn(format!(
+                            "cranelift = false from the {} has no effect, it is overridden by cranelift = true from the {global_cranelift_source}",
+                            SettingSource::Target
+                        ));
+                        explained.record("cranelift", global_cranelift_source);
+                        true
                     }
-                    for l in global_library_directories.iter() {
-                        additional_library_directories.push(l.clone());
+                    (None, Some(false), _) => {
+                        explained.record("cranelift", SettingSource::Target);
+                        false
                     }
-                    for (key, value) in global_environment_variables.iter() {
-                        environment.insert(key.to_owned(), value.to_owned());
+                    (None, None, Some(value)) => {
+                        explained.record("cranelift", global_cranelift_source);
+                        value
                     }
-                    for l in global_apple_sdk.iter() {
-                        apple_sdk_directory.push(l.clone());
+                    (None, None, None) => {
+                        explained.record("cranelift", SettingSource::Default);
+                        false
                     }
-                    (
-                        target,
-                        TargetBuildSettings {
-                            working_dir: Default::default(),
-                            package_or_example: package_or_example.clone(),
-                            features,
-                            asset_folders,
-                            code_watch_folders: self.code_watch_folders.clone(),
-                            environment,
-                            builder: global_builder
-                                .as_ref()
-                                .cloned()
-                                .or(builder)
-                                .unwrap_or_default(),
-                            manifest_path: manifest_path.or(global_manifest.cloned()),
-                            additional_library_directories,
-                            apple_sdk_directory,
-                            craneflift: match cranelift {
-                                Some(true) => true,
-                                _ => matches!(global_cranelift, Some(true)),
-                            },
-                        },
+                };
+
+                let cancel_stale_builds = explained
+                    .pick_first(
+                        "cancel_stale_builds",
+                        [
+                            (SettingSource::Profile, profile_config.cancel_stale_builds),
+                            (SettingSource::Target, target_config.cancel_stale_builds),
+                            (
+                                SettingSource::Package,
+                                package_specific_config.cancel_stale_builds,
+                            ),
+                            (SettingSource::Global, self.cancel_stale_builds),
+                        ],
+                        false,
                     )
-                },
-            )
+                    .unwrap_or_default();
+
+                let profile = explained.pick_first(
+                    "profile",
+                    [
+                        (SettingSource::Profile, profile_config.profile.clone()),
+                        (SettingSource::Target, target_config.profile.clone()),
+                        (SettingSource::Package, package_specific_config.profile.clone()),
+                        (SettingSource::Global, self.profile.clone()),
+                    ],
+                    false,
+                );
+
+                let keep_builds = explained.pick_first(
+                    "keep_builds",
+                    [
+                        (SettingSource::Profile, profile_config.keep_builds),
+                        (SettingSource::Target, target_config.keep_builds),
+                        (SettingSource::Package, package_specific_config.keep_builds),
+                        (SettingSource::Global, self.keep_builds),
+                    ],
+                    false,
+                );
+
+                let linker = explained.pick_first(
+                    "linker",
+                    [
+                        (SettingSource::Profile, profile_config.linker.clone()),
+                        (SettingSource::Target, target_config.linker.clone()),
+                        (SettingSource::Package, package_specific_config.linker.clone()),
+                        (SettingSource::Global, self.linker.clone()),
+                    ],
+                    false,
+                );
+
+                let toolchain = explained.pick_first(
+                    "toolchain",
+                    [
+                        (SettingSource::Profile, profile_config.toolchain.clone()),
+                        (SettingSource::Target, target_config.toolchain.clone()),
+                        (
+                            SettingSource::Package,
+                            package_specific_config.toolchain.clone(),
+                        ),
+                        (SettingSource::Global, self.toolchain.clone()),
+                    ],
+                    false,
+                );
+
+                explained.record("package_or_example", SettingSource::CommandLine);
+                explained.record("working_dir", SettingSource::Default);
+                explained.settings = TargetBuildSettings {
+                    working_dir: Default::default(),
+                    package_or_example: package_or_example.clone(),
+                    features,
+                    asset_folders,
+                    code_watch_folders,
+                    ignore,
+                    environment,
+                    builder,
+                    manifest_path,
+                    additional_library_directories,
+                    apple_sdk_directory,
+                    craneflift,
+                    cancel_stale_builds,
+                    profile,
+                    keep_builds,
+                    linker,
+                    toolchain,
+                    hot_crates,
+                    build_command,
+                };
+                explained
+            })
             .collect::<Vec<_>>())
     }
 }
 
+/// The layer of a config that a build setting came from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SettingSource {
+    /// Passed in as a CLI argument
+    CommandLine,
+    /// The selected profile, in `[profiles]`
+    Profile,
+    /// The section for the current target, in `[targets]`
+    Target,
+    /// The section for the current package or example, in `[packages]`, `[examples]` or `[default_package]`
+    Package,
+    /// The top level of the config
+    Global,
+    /// Not set anywhere, so the default value was used
+    Default,
+}
+
+impl std::fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SettingSource::CommandLine => "command line",
+            SettingSource::Profile => "profile section",
+            SettingSource::Target => "target section",
+            SettingSource::Package => "package section",
+            SettingSource::Global => "global config",
+            SettingSource::Default => "default",
+        })
+    }
+}
+
+/// Build settings for a single target, along with where each setting came from
+#[derive(Debug, Clone)]
+pub struct ExplainedBuildSettings {
+    pub target: Target,
+    pub settings: TargetBuildSettings,
+    /// The layers that supplied each field, in the order they were applied
+    pub sources: Vec<(&'static str, Vec<SettingSource>)>,
+    /// Settings that were provided but have no effect on the final result
+    pub warnings: Vec<String>,
+}
+
+impl ExplainedBuildSettings {
+    fn new(target: Target) -> Self {
+        Self {
+            target,
+            settings: Default::default(),
+            sources: vec![],
+            warnings: vec![],
+        }
+    }
+
+    pub fn sources_for(&self, field: &str) -> &[SettingSource] {
+        self.sources
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, sources)| sources.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn record(&mut self, field: &'static str, source: SettingSource) {
+        match self.sources.iter_mut().find(|(name, _)| *name == field) {
+            Some((_, sources)) => {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+            }
+            None => self.sources.push((field, vec![source])),
+        }
+    }
+
+    fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Uses the value from the first layer that sets one, optionally warning about
+    /// any later layers whose different value ends up ignored.
+    fn pick_first<T: PartialEq + std::fmt::Debug, const N: usize>(
+        &mut self,
+        field: &'static str,
+        layers: [(SettingSource, Option<T>); N],
+        warn_when_overridden: bool,
+    ) -> Option<T> {
+        let mut picked: Option<(SettingSource, T)> = None;
+        for (source, value) in layers {
+            let Some(value) = value else {
+                continue;
+            };
+            match &picked {
+                None => picked = Some((source, value)),
+                Some((picked_source, picked_value)) => {
+                    if warn_when_overridden && *picked_value != value {
+                        self.warn(format!(
+                            "{field} {value:?} from the {source} has no effect, it is overridden by {picked_value:?} from the {picked_source}"
+                        ));
+                    }
+                }
+            }
+        }
+        match picked {
+            Some((source, value)) => {
+                self.record(field, source);
+                Some(value)
+            }
+            None => {
+                self.record(field, SettingSource::Default);
+                None
+            }
+        }
+    }
+
+    fn collect_list<T: Clone + PartialEq + std::fmt::Debug, const N: usize>(
+        &mut self,
+        field: &'static str,
+        layers: [(SettingSource, &Vec<T>); N],
+    ) -> Vec<T> {
+        let mut result = vec![];
+        for (source, values) in layers {
+            for value in values {
+                if result.contains(value) {
+                    self.warn(format!(
+                        "{field} entry {value:?} from the {source} has no effect, it is already set"
+                    ));
+                    continue;
+                }
+                self.record(field, source);
+                result.push(value.clone());
+            }
+        }
+        if result.is_empty() {
+            self.record(field, SettingSource::Default);
+        }
+        result
+    }
+
+    fn collect_environment<const N: usize>(
+        &mut self,
+        layers: [(SettingSource, &HashMap<String, String>); N],
+    ) -> HashMap<String, String> {
+        let mut result = HashMap::<String, (SettingSource, String)>::new();
+        for (source, values) in layers {
+            for (key, value) in values {
+                if let Some((previous_source, previous)) =
+                    result.insert(key.clone(), (source, value.clone()))
+                {
+                    if previous_source != source && previous != *value {
+                        self.warn(format!(
+                            "environment variable {key} from the {previous_source} has no effect, it is overridden by the {source}"
+                        ));
+                    }
+                }
+            }
+        }
+        if result.is_empty() {
+            self.record("environment", SettingSource::Default);
+        }
+        let mut sources = result
+            .values()
+            .map(|(source, _)| *source)
+            .collect::<Vec<_>>();
+        sources.sort_by_key(|source| *source as u8);
+        for source in sources {
+            self.record("environment", source);
+        }
+        result
+            .into_iter()
+            .map(|(key, (_, value))| (key, value))
+            .collect()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum BuildSettingsGenerationError {
     #[error("No Default Target for this Platform")]
     NoDefaultTarget,
+    #[error("No profile named {0} in the config")]
+    UnknownProfile(String),
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{PackageOrExample, Target};
+    use std::collections::HashMap;
+
+    use crate::{Linker, PackageOrExample, Target};
     use camino::Utf8PathBuf;
+    use test_temp_dir::*;
+
+    use super::{
+        BuildSettingsGenerationError, DexterousConfig, LoadConfigError, ReloadTargetConfig,
+        SettingSource, CONFIG_FILE_NAME, LOCAL_CONFIG_FILE_NAME,
+    };
+
+    #[test]
+    fn selecting_a_profile_overlays_its_settings() {
+        let config = DexterousConfig::load_toml_from_str(
+            r#"
+        features = ["hot"]
+        cranelift = true
+
+        [profiles.realistic]
+        features = ["full"]
+        cranelift = false
+        cancel_stale_builds = true
+        profile = "release"
+
+        [profiles.realistic.environment]
+        LOG = "debug"
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let build_settings = config
+            .generate_build_settings(None, &[], None)
+            .expect("Couldn't generate build settings");
+        let (_, settings) = build_settings.first().expect("No Targets Set Up");
+        assert!(settings.craneflift);
+        assert!(!settings.cancel_stale_builds);
+        assert_eq!(settings.cargo_profile(), "dev");
+        assert_eq!(settings.features, vec!["hot"]);
+
+        let build_settings = config
+            .generate_build_settings(None, &[], Some("realistic"))
+            .expect("Couldn't generate build settings");
+        let (_, settings) = build_settings.first().expect("No Targets Set Up");
+        assert!(!settings.craneflift);
+        assert!(settings.cancel_stale_builds);
+        assert_eq!(settings.cargo_profile(), "release");
+        assert_eq!(settings.features, vec!["full", "hot"]);
+        assert_eq!(settings.environment.get("LOG").unwrap(), "debug");
+
+        assert!(matches!(
+            config.generate_build_settings(None, &[], Some("missing")),
+            Err(BuildSettingsGenerationError::UnknownProfile(_))
+        ));
+    }
+
+    #[test]
+    fn parses_linker_choices() {
+        let config = DexterousConfig::load_toml_from_str(
+            r#"
+        linker = "mold"
+
+        [profiles.system]
+        linker = "system"
+
+        [profiles.custom]
+        linker = { custom = "/opt/bin/my-linker" }
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let linker = |profile| {
+            let build_settings = config
+                .generate_build_settings(None, &[], profile)
+                .expect("Couldn't generate build settings");
+            let (_, settings) = build_settings.first().expect("No Targets Set Up");
+            settings.linker.clone()
+        };
+
+        assert_eq!(linker(None), Some(Linker::Mold));
+        assert_eq!(linker(Some("system")), Some(Linker::System));
+        assert_eq!(
+            linker(Some("custom")),
+            Some(Linker::Custom(Utf8PathBuf::from("/opt/bin/my-linker")))
+        );
+    }
+
+    #[test]
+    fn selects_a_toolchain_per_target() {
+        let config = DexterousConfig::load_toml_from_str(
+            r#"
+        toolchain = "1.79.0"
+
+        [targets.x86_64-unknown-linux-gnu]
+        features = ["linux"]
+
+        [targets.x86_64-pc-windows-msvc]
+        toolchain = "nightly-2024-05-01"
+
+        [profiles.local]
+        toolchain = "/opt/rust/toolchains/custom"
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let toolchain = |profile| {
+            let build_settings = config
+                .generate_build_settings(None, &[], profile)
+                .expect("Couldn't generate build settings");
+            build_settings
+                .into_iter()
+                .map(|(target, settings)| (target, settings.toolchain))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let toolchains = toolchain(None);
+        assert_eq!(
+            toolchains.get(&Target::Linux),
+            Some(&Some("1.79.0".to_string()))
+        );
+        assert_eq!(
+            toolchains.get(&Target::Windows),
+            Some(&Some("nightly-2024-05-01".to_string()))
+        );
+        let local = toolchain(Some("local"));
+        assert!(local
+            .values()
+            .all(|toolchain| toolchain.as_deref() == Some("/opt/rust/toolchains/custom")));
+    }
+
+    #[test]
+    fn collects_hot_crates_from_every_layer() {
+        let config = DexterousConfig::load_toml_from_str(
+            r#"
+        hot_crates = ["gameplay"]
+
+        [targets.x86_64-unknown-linux-gnu]
+        hot_crates = ["physics"]
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let explained = config
+            .explain_build_settings(None, &[], None)
+            .expect("Couldn't explain build settings");
+
+        assert_eq!(explained.len(), 1);
+        let explained = explained.first().unwrap();
+
+        assert_eq!(explained.settings.hot_crates, vec!["physics", "gameplay"]);
+        assert_eq!(
+            explained.sources_for("hot_crates"),
+            &[SettingSource::Target, SettingSource::Global]
+        );
+        assert!(explained.warnings.is_empty(), "{:?}", explained.warnings);
+    }
 
-    use super::{DexterousConfig, ReloadTargetConfig};
+    #[test]
+    fn skips_list_entries_that_are_already_set() {
+        let config = DexterousConfig::load_toml_from_str(
+            r#"
+        features = ["hot", "shared"]
+
+        [targets.x86_64-unknown-linux-gnu]
+        features = ["shared"]
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let explained = config
+            .explain_build_settings(None, &[], None)
+            .expect("Couldn't explain build settings");
+        let explained = explained.first().unwrap();
+
+        assert_eq!(explained.settings.features, vec!["shared", "hot"]);
+        assert_eq!(explained.warnings.len(), 1, "{:?}", explained.warnings);
+        assert!(explained.warnings[0].contains("\"shared\""));
+    }
+
+    #[test]
+    fn interpolates_variables_and_loads_env_files() {
+        let directory = test_temp_dir!();
+        let directory =
+            Utf8PathBuf::from_path_buf(directory.as_path_untracked().to_path_buf()).unwrap();
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("dev.env"),
+            "ASSET_ROOT=/from/file\nFROM_FILE=file\nLOG=info\n",
+        )
+        .unwrap();
+
+        let mut config = DexterousConfig::load_toml_from_str(
+            r#"
+        env_file = "dev.env"
+        asset_folders = ["${ASSET_ROOT}/assets", "${MISSING:-fallback}/assets", "${EMPTY:-empty}/assets", "${EMPTY}/assets"]
+        additional_library_directories = ["${HOME_DIR}/libs"]
+
+        [environment]
+        LOG = "debug"
+        PRICE = "$$5"
+
+        [packages.game.environment]
+        PATH_WITH_ROOT = "${ASSET_ROOT}"
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let lookup = |name: &str| match name {
+            "HOME_DIR" => Some("/home/me".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        config
+            .resolve_variables(&directory, &lookup)
+            .expect("Couldn't resolve variables");
+
+        assert_eq!(
+            config.asset_folders,
+            vec![
+                Utf8PathBuf::from("/from/file/assets"),
+                Utf8PathBuf::from("fallback/assets"),
+                Utf8PathBuf::from("empty/assets"),
+                Utf8PathBuf::from("/assets")
+            ]
+        );
+        assert_eq!(
+            config.additional_library_directories,
+            vec![Utf8PathBuf::from("/home/me/libs")]
+        );
+        assert_eq!(config.environment.get("LOG").unwrap(), "debug");
+        assert_eq!(config.environment.get("FROM_FILE").unwrap(), "file");
+        assert_eq!(config.environment.get("PRICE").unwrap(), "$5");
+
+        let package = config.packages.get("game").unwrap();
+        assert_eq!(
+            package.environment.get("PATH_WITH_ROOT").unwrap(),
+            "/from/file"
+        );
+        assert!(!package.environment.contains_key("FROM_FILE"));
+
+        let mut missing = DexterousConfig::load_toml_from_str(
+            r#"
+        asset_folders = ["${MISSING}/assets"]
+        "#,
+        )
+        .expect("Couldn't load toml");
+        assert!(matches!(
+            missing.resolve_variables(&directory, &|_| None),
+            Err(LoadConfigError::MissingVariable { variable, .. }) if variable == "MISSING"
+        ));
+
+        let mut no_file = DexterousConfig::load_toml_from_str(
+            r#"
+        env_file = "missing.env"
+        "#,
+        )
+        .expect("Couldn't load toml");
+        assert!(matches!(
+            no_file.resolve_variables(&directory, &|_| None),
+            Err(LoadConfigError::EnvFileError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn loads_every_layer_with_later_layers_taking_precedence() {
+        let directory = test_temp_dir!();
+        let workspace =
+            Utf8PathBuf::from_path_buf(directory.as_path_untracked().to_path_buf()).unwrap();
+        let package = workspace.join("game");
+        std::fs::create_dir_all(&package).unwrap();
+        let workspace = workspace.canonicalize_utf8().unwrap();
+        let package = package.canonicalize_utf8().unwrap();
+
+        std::fs::write(
+            workspace.join(CONFIG_FILE_NAME),
+            r#"
+        port = 1
+        keep_builds = 1
+        cancel_stale_builds = false
+        asset_folders = ["assets"]
+        "#,
+        )
+        .unwrap();
+        std::fs::write(
+            package.join(CONFIG_FILE_NAME),
+            r#"
+        port = 2
+        keep_builds = 2
+        cancel_stale_builds = true
+        asset_folders = ["assets"]
+        "#,
+        )
+        .unwrap();
+        std::fs::write(
+            workspace.join(LOCAL_CONFIG_FILE_NAME),
+            r#"
+        port = 3
+        keep_builds = 3
+        code_watch_folders = ["src"]
+        "#,
+        )
+        .unwrap();
+        std::fs::write(
+            package.join(LOCAL_CONFIG_FILE_NAME),
+            r#"
+        port = 4
+        additional_library_directories = ["libs"]
+        "#,
+        )
+        .unwrap();
+
+        let config = DexterousConfig::load_layered(&workspace, Some(&package))
+            .await
+            .expect("Couldn't load layered config");
+
+        assert_eq!(config.port, Some(4));
+        assert_eq!(config.keep_builds, Some(3));
+        assert_eq!(config.cancel_stale_builds, Some(true));
+        assert_eq!(
+            config.asset_folders,
+            vec![workspace.join("assets"), package.join("assets")]
+        );
+        assert_eq!(config.code_watch_folders, vec![workspace.join("src")]);
+        assert_eq!(
+            config.additional_library_directories,
+            vec![package.join("libs")]
+        );
+    }
+
+    #[test]
+    fn strict_parsing_reports_unknown_keys_with_their_lines() {
+        let toml = r#"features = ["hot"]
+featurs = ["typo"]
+
+[targets.x86_64-unknown-linux-gnu]
+cranelift = true
+cranelfit = false
+
+[packages.game]
+environment = { LOG = "debug" }
+asset_folder = ["./assets"]
+"#;
+
+        DexterousConfig::load_toml_from_str(toml).expect("Non-strict parsing should ignore typos");
+
+        let Err(LoadConfigError::UnknownKeys(keys)) =
+            DexterousConfig::load_toml_from_str_strict(toml)
+        else {
+            panic!("Strict parsing should fail");
+        };
+        let mut keys = keys
+            .into_iter()
+            .map(|key| (key.path, key.line))
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                ("featurs".to_string(), Some(2)),
+                ("packages.game.asset_folder".to_string(), Some(10)),
+                (
+                    "targets.x86_64-unknown-linux-gnu.cranelfit".to_string(),
+                    Some(6)
+                ),
+            ]
+        );
+
+        DexterousConfig::load_toml_from_str_strict("features = [\"hot\"]")
+            .expect("Valid config should parse in strict mode");
+    }
+
+    #[test]
+    fn json_schema_describes_the_config() {
+        let schema = serde_json::to_value(DexterousConfig::json_schema()).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        assert!(properties.contains_key("code_watch_folders"));
+        assert!(properties.contains_key("targets"));
+        assert!(schema["definitions"]["ReloadTargetConfig"]["properties"]
+            .as_object()
+            .unwrap()
+            .contains_key("builder"));
+    }
+
+    #[test]
+    fn json_schema_rejects_unknown_keys() {
+        let schema = serde_json::to_value(DexterousConfig::json_schema()).unwrap();
+        assert_eq!(schema["additionalProperties"], serde_json::json!(false));
+        assert_eq!(
+            schema["definitions"]["ReloadTargetConfig"]["additionalProperties"],
+            serde_json::json!(false)
+        );
+
+        // Loading still only reports unknown keys in strict mode
+        let config = DexterousConfig::load_toml_from_str("featurs = [\"hot\"]")
+            .expect("Unknown keys should be ignored outside strict mode");
+        assert!(config.features.is_empty());
+    }
+
+    #[test]
+    fn reads_config_from_cargo_metadata() {
+        let metadata = serde_json::json!({
+            "other-tool": { "setting": true },
+            "dexterous": {
+                "features": ["hot"],
+                "code_watch_folders": ["./src"]
+            }
+        });
+
+        let config = DexterousConfig::from_cargo_metadata(&metadata)
+            .expect("Couldn't parse metadata")
+            .expect("No dexterous metadata found");
+
+        assert_eq!(config.features, vec!["hot"]);
+        assert_eq!(config.code_watch_folders, vec![Utf8PathBuf::from("./src")]);
+
+        let missing = DexterousConfig::from_cargo_metadata(&serde_json::json!({}))
+            .expect("Couldn't parse metadata");
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn explaining_build_settings_reports_sources_and_ineffective_settings() {
+        let config = DexterousConfig::load_toml_from_str(
+            r#"
+        features = ["global-feature"]
+        cranelift = true
+
+        [environment]
+        SHARED = "global"
+
+        [targets.x86_64-pc-windows-msvc]
+        cranelift = false
+
+        [targets.x86_64-pc-windows-msvc.environment]
+        SHARED = "target"
+        "#,
+        )
+        .expect("Couldn't load toml");
+
+        let explained = config
+            .explain_build_settings(None, &["cli-feature".to_string()], None)
+            .expect("Couldn't explain build settings");
+
+        assert_eq!(explained.len(), 1);
+        let explained = explained.first().unwrap();
+
+        assert_eq!(explained.target, Target::Windows);
+        assert_eq!(
+            explained.sources_for("features"),
+            &[SettingSource::CommandLine, SettingSource::Global]
+        );
+        assert_eq!(explained.sources_for("cranelift"), &[SettingSource::Global]);
+        assert_eq!(
+            explained.sources_for("asset_folders"),
+            &[SettingSource::Default]
+        );
+        assert!(explained.settings.craneflift);
+        assert_eq!(
+            explained.settings.environment.get("SHARED").unwrap(),
+            "global"
+        );
+        assert_eq!(explained.warnings.len(), 2, "{:?}", explained.warnings);
+        assert!(explained
+            .warnings
+            .iter()
+            .any(|w| w.starts_with("cranelift = false")));
+        assert!(explained
+            .warnings
+            .iter()
+            .any(|w| w.starts_with("environment variable SHARED")));
+    }
+
+    #[test]
+    fn merging_a_config_layer_overrides_values_and_extends_lists() {
+        let base = DexterousConfig::load_toml_from_str(
+            r#"
+        features = ["base-feature"]
+        port = 1234
+        cranelift = true
+
+        [environment]
+        SHARED = "base"
+        BASE_ONLY = "base"
+
+        [packages.my-package]
+        features = ["package-feature"]
+        cranelift = true
+        "#,
+        )
+        .expect("Couldn't load base toml");
+
+        let overlay = DexterousConfig::load_toml_from_str(
+            r#"
+        features = ["overlay-feature", "base-feature"]
+        port = 4321
+
+        [environment]
+        SHARED = "overlay"
+
+        [packages.my-package]
+        cranelift = false
+        "#,
+        )
+        .expect("Couldn't load overlay toml");
+
+        let merged = base.merge(overlay);
+
+        assert_eq!(merged.features, vec!["base-feature", "overlay-feature"]);
+        assert_eq!(merged.port, Some(4321));
+        assert_eq!(merged.cranelift, Some(true));
+        assert_eq!(merged.environment.get("SHARED").unwrap(), "overlay");
+        assert_eq!(merged.environment.get("BASE_ONLY").unwrap(), "base");
+
+        let package = merged
+            .packages
+            .get("my-package")
+            .expect("Package section missing");
+        assert_eq!(package.features, vec!["package-feature"]);
+        assert_eq!(package.cranelift, Some(false));
+    }
 
     #[test]
     fn given_a_manifest_with_no_metadata_provides_default_target() {
@@ -268,7 +1818,7 @@ mod test {
 
         let config = DexterousConfig::load_toml_from_str(toml).expect("Couldn't load toml");
         let build_settings = config
-            .generate_build_settings(None, &[])
+            .generate_build_settings(None, &[], None)
             .expect("Couldn't generate build settings");
 
         assert_eq!(build_settings.len(), 1);
@@ -293,14 +1843,24 @@ mod test {
                 ReloadTargetConfig {
                     features: vec!["my-feature".to_string()],
                     asset_folders: vec![Utf8PathBuf::from("/asset")],
+                    code_watch_folders: vec![],
+                    ignore: vec![],
                     environment: [("env".to_string(), "value".to_string())]
                         .into_iter()
                         .collect(),
+                    env_file: None,
                     builder: None,
+                    build_command: None,
                     manifest_path: None,
                     additional_library_directories: vec![],
                     apple_sdk_directory: vec![],
                     cranelift: None,
+                    cancel_stale_builds: None,
+                    profile: None,
+                    keep_builds: None,
+                    linker: None,
+                    toolchain: None,
+                    hot_crates: vec![],
                 },
             )])
             .into_iter()
@@ -309,7 +1869,7 @@ mod test {
         };
 
         let build_settings = config
-            .generate_build_settings(None, &[])
+            .generate_build_settings(None, &[], None)
             .expect("Couldn't generate build settings");
 
         assert_eq!(build_settings.len(), 1);
@@ -343,7 +1903,7 @@ mod test {
         };
 
         let build_settings = config
-            .generate_build_settings(None, &[])
+            .generate_build_settings(None, &[], None)
             .expect("Couldn't generate build settings");
 
         assert_eq!(build_settings.len(), 1);
@@ -397,6 +1957,7 @@ mod test {
             .generate_build_settings(
                 Some(PackageOrExample::Package("My-Package".to_string())),
                 &[],
+                None,
             )
             .expect("Couldn't generate build settings");
 
@@ -454,6 +2015,7 @@ mod test {
             .generate_build_settings(
                 Some(PackageOrExample::Example("My-Example".to_string())),
                 &[],
+                None,
             )
             .expect("Couldn't generate build settings");
 
@@ -489,7 +2051,7 @@ mod test {
         };
 
         let build_settings = config
-            .generate_build_settings(None, &["my-feature".to_string()])
+            .generate_build_settings(None, &["my-feature".to_string()], None)
             .expect("Couldn't generate build settings");
 
         assert_eq!(build_settings.len(), 1);
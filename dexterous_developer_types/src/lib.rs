@@ -3,12 +3,12 @@ pub mod cargo_path_utils;
 #[cfg(feature = "config")]
 pub mod config;
 
-use std::{collections::HashMap, fmt::Display, ops::Deref, str::FromStr};
+use std::{collections::HashMap, fmt::Display, ops::Deref, str::FromStr, time::Duration};
 
 use camino::Utf8PathBuf;
 use serde::{de, Deserialize, Deserializer, Serialize};
 use thiserror::Error;
-use tracing::debug;
+use tracing::{debug, warn};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LibraryPath {
@@ -35,9 +35,50 @@ pub enum PackageOrExample {
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(schemars::JsonSchema))]
 pub enum BuilderTypes {
     #[default]
     Default,
+    /// Runs the `build_command` from the config, which reports its output as JSON lines
+    Command,
+}
+
+/// The linker used for hot reload builds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "config", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Linker {
+    /// LLVM's lld
+    Lld,
+    /// The mold linker
+    Mold,
+    /// Whatever rustc uses for the target without any extra flags
+    System,
+    /// A specific linker executable, passed to rustc with `-C linker`
+    #[cfg_attr(feature = "config", schemars(with = "String"))]
+    Custom(Utf8PathBuf),
+}
+
+impl Linker {
+    /// The linker used when none is configured - lld where it is known to help, and the system linker elsewhere
+    pub fn default_for(target: &Target) -> Self {
+        if matches!(target, Target::Linux | Target::LinuxArm | Target::Windows) {
+            Linker::Lld
+        } else {
+            Linker::System
+        }
+    }
+}
+
+impl Display for Linker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Linker::Lld => f.write_str("lld"),
+            Linker::Mold => f.write_str("mold"),
+            Linker::System => f.write_str("the system linker"),
+            Linker::Custom(path) => write!(f, "{path}"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -48,43 +89,149 @@ pub struct TargetBuildSettings {
     pub features: Vec<String>,
     pub asset_folders: Vec<camino::Utf8PathBuf>,
     pub code_watch_folders: Vec<camino::Utf8PathBuf>,
+    /// Glob patterns for files within the code watch folders that shouldn't trigger a rebuild
+    pub ignore: Vec<String>,
     pub environment: HashMap<String, String>,
     pub builder: BuilderTypes,
     pub additional_library_directories: Vec<Utf8PathBuf>,
     pub apple_sdk_directory: Vec<Utf8PathBuf>,
     pub craneflift: bool,
+    /// Stop a running build as soon as code changes, instead of waiting for it to finish
+    pub cancel_stale_builds: bool,
+    /// The cargo profile to build with - `dev` if not set
+    pub profile: Option<String>,
+    /// How many of the most recent builds' libraries to keep on disk
+    pub keep_builds: Option<u32>,
+    /// The linker to use - the target's default if not set
+    pub linker: Option<Linker>,
+    /// The rustup toolchain to build with, as a channel or a path - the ambient toolchain if not set
+    pub toolchain: Option<String>,
+    /// Workspace crates compiled into their own versioned libraries, alongside the root library
+    pub hot_crates: Vec<String>,
+    /// The program and arguments run by the command builder
+    pub build_command: Vec<String>,
+}
+
+impl TargetBuildSettings {
+    /// The cargo profile to build with
+    pub fn cargo_profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or("dev")
+    }
+
+    /// How many of the most recent builds' libraries to keep on disk - at least one
+    pub fn builds_to_keep(&self) -> usize {
+        self.keep_builds.unwrap_or(10).max(1) as usize
+    }
+
+    /// The rustup toolchain to build with - `nightly` when building with cranelift and no toolchain is set
+    pub fn rust_toolchain(&self) -> Option<&str> {
+        self.toolchain
+            .as_deref()
+            .or(self.craneflift.then_some("nightly"))
+    }
+
+    /// The linker to use for a target
+    pub fn linker_for(&self, target: &Target) -> Linker {
+        self.linker
+            .clone()
+            .unwrap_or_else(|| Linker::default_for(target))
+    }
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+/// The directory within `target/<triple>` that cargo places a profile's output in
+pub fn profile_directory(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        custom => custom,
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Target {
     Linux,
     LinuxArm,
+    LinuxMusl,
+    LinuxArmv7,
+    LinuxRiscV64,
     Windows,
     Mac,
     MacArm,
     Android,
     IOS,
+    /// Any other target triple - passed to cargo exactly as written
+    Custom(String),
 }
 
 impl Target {
+    /// The target this was compiled for
+    ///
+    /// Hosts without a matching variant, such as musl on aarch64, are returned as [`Target::Custom`]
     pub fn current() -> Option<Self> {
-        if cfg!(target_os = "linux") {
-            if cfg!(target_arch = "aarch64") {
-                Some(Self::LinuxArm)
+        let arch = match std::env::consts::ARCH {
+            "arm" if cfg!(target_feature = "v7") => "armv7",
+            "riscv64" => "riscv64gc",
+            "x86" => "i686",
+            "powerpc64" if cfg!(target_endian = "little") => "powerpc64le",
+            arch => arch,
+        };
+
+        let triple = if cfg!(target_os = "linux") {
+            let env = if cfg!(target_env = "musl") {
+                "musl"
+            } else if cfg!(target_env = "gnu") {
+                "gnu"
             } else {
-                Some(Self::Linux)
-            }
+                return None;
+            };
+            let abi = if cfg!(target_abi = "eabihf") {
+                "eabihf"
+            } else if cfg!(target_abi = "eabi") {
+                "eabi"
+            } else {
+                ""
+            };
+            format!("{arch}-unknown-linux-{env}{abi}")
         } else if cfg!(target_os = "windows") {
-            Some(Self::Windows)
-        } else if cfg!(target_os = "macos") {
-            if cfg!(target_arch = "aarch64") {
-                Some(Self::MacArm)
+            let env = if cfg!(target_env = "gnu") {
+                "gnu"
             } else {
-                Some(Self::Mac)
-            }
+                "msvc"
+            };
+            format!("{arch}-pc-windows-{env}")
+        } else if cfg!(target_os = "macos") {
+            format!("{arch}-apple-darwin")
         } else {
-            None
+            return None;
+        };
+
+        Some(Self::from_triple(triple))
+    }
+
+    /// Picks the variant for a known triple, or a [`Target::Custom`] for anything else
+    fn from_triple(triple: String) -> Self {
+        KNOWN_TARGETS
+            .iter()
+            .find(|known| known.as_str() == triple)
+            .cloned()
+            .unwrap_or(Self::Custom(triple))
+    }
+
+    /// The targets older versions accepted by platform name, such as `linux` or `mac-arm`
+    fn from_legacy_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let platform = name.split('-').next()?;
+        let arm = name.contains("arm") || name.contains("aarch");
+        match platform {
+            "linux" if arm => Some(Self::LinuxArm),
+            "linux" if name.contains("musl") => Some(Self::LinuxMusl),
+            "linux" => Some(Self::Linux),
+            "windows" | "win" => Some(Self::Windows),
+            "mac" | "macos" | "darwin" | "osx" if arm => Some(Self::MacArm),
+            "mac" | "macos" | "darwin" | "osx" => Some(Self::Mac),
+            "android" => Some(Self::Android),
+            "ios" => Some(Self::IOS),
+            _ => None,
         }
     }
 }
@@ -94,7 +241,7 @@ impl Serialize for Target {
     where
         S: serde::Serializer,
     {
-        serializer.collect_str(self.to_static())
+        serializer.collect_str(self.as_str())
     }
 }
 
@@ -108,33 +255,60 @@ impl<'de> Deserialize<'de> for Target {
     }
 }
 
+const KNOWN_TARGETS: [Target; 10] = [
+    Target::Linux,
+    Target::LinuxArm,
+    Target::LinuxMusl,
+    Target::LinuxArmv7,
+    Target::LinuxRiscV64,
+    Target::Windows,
+    Target::Mac,
+    Target::MacArm,
+    Target::Android,
+    Target::IOS,
+];
+
 impl Target {
-    pub const fn to_static(self) -> &'static str {
+    /// The target triple, as passed to `cargo --target`
+    pub fn as_str(&self) -> &str {
         match self {
             Target::Linux => "x86_64-unknown-linux-gnu",
             Target::LinuxArm => "aarch64-unknown-linux-gnu",
+            Target::LinuxMusl => "x86_64-unknown-linux-musl",
+            Target::LinuxArmv7 => "armv7-unknown-linux-gnueabihf",
+            Target::LinuxRiscV64 => "riscv64gc-unknown-linux-gnu",
             Target::Windows => "x86_64-pc-windows-msvc",
             Target::Mac => "x86_64-apple-darwin",
             Target::MacArm => "aarch64-apple-darwin",
             Target::Android => "aarch64-linux-android",
             Target::IOS => "aarch64-apple-ios",
+            Target::Custom(triple) => triple,
         }
     }
 
-    pub const fn dynamic_lib_extension(&self) -> &'static str {
-        match self {
-            Target::Windows => "dll",
-            Target::Mac => "dylib",
-            Target::MacArm => "dylib",
-            Target::IOS => "dylib",
-            _ => "so",
+    pub fn is_windows(&self) -> bool {
+        self.as_str().contains("-windows")
+    }
+
+    pub fn is_apple(&self) -> bool {
+        self.as_str().contains("-apple-")
+    }
+
+    pub fn dynamic_lib_extension(&self) -> &'static str {
+        if self.is_windows() {
+            "dll"
+        } else if self.is_apple() {
+            "dylib"
+        } else {
+            "so"
         }
     }
 
-    pub const fn dynamic_lib_prefix(&self) -> &'static str {
-        match self {
-            Target::Windows => "",
-            _ => "lib",
+    pub fn dynamic_lib_prefix(&self) -> &'static str {
+        if self.is_windows() {
+            ""
+        } else {
+            "lib"
         }
     }
 
@@ -143,10 +317,6 @@ impl Target {
         let extension = self.dynamic_lib_extension();
         format!("{prefix}{name}.{extension}")
     }
-
-    pub const fn as_str(&self) -> &'static str {
-        self.to_static()
-    }
 }
 
 impl Display for Target {
@@ -165,35 +335,42 @@ impl Deref for Target {
 
 #[derive(Error, Debug, Serialize, Deserialize)]
 pub enum TargetParseError {
-    #[error("Couldn't Parse Target")]
-    InvalidTarget,
+    #[error(
+        "Couldn't Parse Target {0:?} - expected a target triple such as x86_64-unknown-linux-gnu"
+    )]
+    InvalidTarget(String),
 }
 
 impl FromStr for Target {
     type Err = TargetParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.trim().to_lowercase();
-        if s.contains("windows") {
-            Ok(Self::Windows)
-        } else if s.contains("android") {
-            Ok(Self::Android)
-        } else if s.contains("linux") {
-            if s.contains("arm") || s.contains("aarch") {
-                Ok(Self::LinuxArm)
-            } else {
-                Ok(Self::Linux)
-            }
-        } else if s.contains("darwin") || s.contains("mac") {
-            if s.contains("arm") || s.contains("aarch") {
-                Ok(Self::MacArm)
-            } else {
-                Ok(Self::Mac)
-            }
-        } else if s.contains("ios") {
-            Ok(Self::IOS)
+        let triple = s.trim();
+
+        if let Some(known) = KNOWN_TARGETS.iter().find(|t| t.as_str() == triple) {
+            return Ok(known.clone());
+        }
+
+        if let Some(target) = Self::from_legacy_name(triple) {
+            warn!(
+                "The target name {triple:?} is deprecated - use the target triple {target} instead"
+            );
+            return Ok(target);
+        }
+
+        let components = triple.split('-').collect::<Vec<_>>();
+        let valid = (2..=4).contains(&components.len())
+            && components.iter().all(|component| {
+                !component.is_empty()
+                    && component
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            });
+
+        if valid {
+            Ok(Self::Custom(triple.to_string()))
         } else {
-            Err(TargetParseError::InvalidTarget)
+            Err(TargetParseError::InvalidTarget(triple.to_string()))
         }
     }
 }
@@ -212,9 +389,383 @@ pub enum HotReloadMessage {
     UpdatedAssets(Utf8PathBuf, [u8; 32]),
     KeepAlive,
     BuildStarted(u32),
+    /// `libraries` only holds the libraries that changed since the previous build -
+    /// `unchanged` names the ones the client already has and should keep
     BuildCompleted {
         id: u32,
         libraries: Vec<(String, [u8; 32], Vec<String>)>,
+        unchanged: Vec<String>,
         root_library: String,
     },
+    /// Compiler output for a build - a build can report several batches as it runs.
+    /// Only sent to clients that negotiated [`Capability::Diagnostics`]
+    Diagnostics {
+        id: u32,
+        diagnostics: Vec<BuildDiagnostic>,
+    },
+    /// Sent whenever a crate finishes compiling, and once more when the build ends.
+    /// Only sent to clients that negotiated [`Capability::BuildProgress`]
+    BuildProgress(BuildProgress),
+}
+
+/// Messages a runner sends to the server once the handshake is complete
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RunnerMessage {
+    /// The runner loaded the libraries from this build, and keeps them loaded until it disconnects
+    LoadedBuild(u32),
+    /// How long the runner took to download and load a build.
+    /// Only sent to servers that negotiated [`Capability::Timings`]
+    Timings {
+        build_id: u32,
+        timings: Vec<PhaseTiming>,
+    },
+}
+
+/// A step in getting a change from source code into a running app
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildPhase {
+    /// Reading `cargo metadata`, or checking the cached copy is still current
+    Metadata,
+    /// From starting cargo until the last crate other than the reloaded one is built
+    Dependencies,
+    /// From the last dependency being built until cargo exits - mostly compiling and linking the reloaded crate,
+    /// but it also covers any crates cargo builds alongside it. Linking isn't timed separately, since rustc
+    /// compiles and links the reloaded crate in a single invocation
+    ReloadedCrate,
+    /// Finding the toolchain and listing the directories libraries are searched for in
+    ToolchainScan,
+    /// Working out which libraries the reloaded library depends on
+    DependencyResolution,
+    /// Hashing the libraries that might have changed
+    Hashing,
+    /// Downloading a build's changed libraries to a runner
+    Download,
+    /// Loading a build into a running app, including waiting for the app to be ready for it
+    Load,
+}
+
+impl Display for BuildPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BuildPhase::Metadata => "metadata",
+            BuildPhase::Dependencies => "dependencies",
+            BuildPhase::ReloadedCrate => "reloaded_crate",
+            BuildPhase::ToolchainScan => "toolchain_scan",
+            BuildPhase::DependencyResolution => "dependency_resolution",
+            BuildPhase::Hashing => "hashing",
+            BuildPhase::Download => "download",
+            BuildPhase::Load => "load",
+        })
+    }
+}
+
+/// How long a [`BuildPhase`] took
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub phase: BuildPhase,
+    pub duration: Duration,
+}
+
+impl PhaseTiming {
+    pub fn new(phase: BuildPhase, duration: Duration) -> Self {
+        Self { phase, duration }
+    }
+}
+
+/// How far along a running build is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildProgress {
+    pub build_id: u32,
+    /// The crate that finished compiling most recently
+    pub current_crate: String,
+    /// How many crates have been compiled, or found to be up to date, so far
+    pub compiled: u32,
+    /// How many crates the previous successful build went through, if there was one
+    pub estimated_total: Option<u32>,
+    /// The time since the build started
+    pub elapsed: Duration,
+    /// Set on the final progress message of a build, once cargo is done
+    pub finished: bool,
+}
+
+/// How severe a [`BuildDiagnostic`] is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Note,
+    Help,
+    /// Any other level, such as an internal compiler error
+    Other,
+}
+
+/// The part of a source file a [`BuildDiagnostic`] points at
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticSpan {
+    /// The file, relative to the workspace root
+    pub file: Utf8PathBuf,
+    pub line_start: usize,
+    pub line_end: usize,
+    pub column_start: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+/// An error or warning produced while building
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildDiagnostic {
+    pub level: DiagnosticLevel,
+    pub message: String,
+    /// The lint or error code, such as `E0308`
+    pub code: Option<String>,
+    pub spans: Vec<DiagnosticSpan>,
+    /// The diagnostic formatted the way the compiler prints it
+    pub rendered: Option<String>,
+}
+
+impl BuildDiagnostic {
+    /// An error that didn't come from the compiler, so has no source location
+    pub fn error(message: impl ToString) -> Self {
+        Self {
+            level: DiagnosticLevel::Error,
+            message: message.to_string(),
+            code: None,
+            spans: vec![],
+            rendered: None,
+        }
+    }
+}
+
+/// The version of the hot reload protocol - increased whenever [`HotReloadMessage`] changes
+/// in a way older peers can't parse
+pub const PROTOCOL_VERSION: u32 = 2;
+/// The oldest protocol version this release can still talk to
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 2;
+/// The optional protocol features this release supports
+pub const SUPPORTED_CAPABILITIES: &[Capability] = &[
+    Capability::Diagnostics,
+    Capability::BuildProgress,
+    Capability::Timings,
+];
+
+/// Optional protocol features, negotiated during the handshake
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// The client understands [`HotReloadMessage::Diagnostics`]
+    Diagnostics,
+    /// The client understands [`HotReloadMessage::BuildProgress`]
+    BuildProgress,
+    /// The server understands [`RunnerMessage::Timings`]
+    Timings,
+    /// A capability from a newer release that this one doesn't know about
+    #[serde(other)]
+    Unknown,
+}
+
+/// Sent by the runner as the first message on the connection
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClientHandshake {
+    pub protocol_version: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+impl Default for ClientHandshake {
+    fn default() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: SUPPORTED_CAPABILITIES.to_vec(),
+        }
+    }
+}
+
+/// The server's reply to a [`ClientHandshake`]. Sent before any [`HotReloadMessage`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ServerHandshake {
+    Accepted {
+        protocol_version: u32,
+        capabilities: Vec<Capability>,
+    },
+    Rejected {
+        server_protocol_version: u32,
+        reason: String,
+    },
+}
+
+impl ServerHandshake {
+    /// Decides whether the server can talk to a client, and which capabilities both sides share
+    pub fn negotiate(client: &ClientHandshake) -> Self {
+        if !(MIN_SUPPORTED_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&client.protocol_version) {
+            return Self::Rejected {
+                server_protocol_version: PROTOCOL_VERSION,
+                reason: format!(
+                    "client uses protocol version {}, but the server supports versions {MIN_SUPPORTED_PROTOCOL_VERSION} to {PROTOCOL_VERSION} - make sure the runner and the cli come from the same release",
+                    client.protocol_version
+                ),
+            };
+        }
+
+        Self::Accepted {
+            protocol_version: client.protocol_version,
+            capabilities: SUPPORTED_CAPABILITIES
+                .iter()
+                .filter(|capability| client.capabilities.contains(capability))
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        profile_directory, Capability, ClientHandshake, ServerHandshake, Target, PROTOCOL_VERSION,
+    };
+
+    #[test]
+    fn handshake_accepts_clients_with_the_same_protocol_version() {
+        let response = ServerHandshake::negotiate(&ClientHandshake::default());
+        assert_eq!(
+            response,
+            ServerHandshake::Accepted {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: vec![
+                    Capability::Diagnostics,
+                    Capability::BuildProgress,
+                    Capability::Timings
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn handshake_only_enables_capabilities_the_client_supports() {
+        let response = ServerHandshake::negotiate(&ClientHandshake {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: vec![Capability::Unknown],
+        });
+        assert_eq!(
+            response,
+            ServerHandshake::Accepted {
+                protocol_version: PROTOCOL_VERSION,
+                capabilities: vec![]
+            }
+        );
+    }
+
+    #[test]
+    fn handshake_rejects_clients_with_an_unsupported_protocol_version() {
+        let response = ServerHandshake::negotiate(&ClientHandshake {
+            protocol_version: PROTOCOL_VERSION + 1,
+            capabilities: vec![],
+        });
+        let ServerHandshake::Rejected {
+            server_protocol_version,
+            reason,
+        } = response
+        else {
+            panic!("Handshake should be rejected");
+        };
+        assert_eq!(server_protocol_version, PROTOCOL_VERSION);
+        assert!(reason.contains(&(PROTOCOL_VERSION + 1).to_string()));
+    }
+
+    #[test]
+    fn profiles_map_to_cargo_output_directories() {
+        assert_eq!(profile_directory("dev"), "debug");
+        assert_eq!(profile_directory("test"), "debug");
+        assert_eq!(profile_directory("release"), "release");
+        assert_eq!(profile_directory("bench"), "release");
+        assert_eq!(profile_directory("hot"), "hot");
+    }
+
+    #[test]
+    fn known_target_triples_round_trip() {
+        for triple in [
+            "x86_64-unknown-linux-gnu",
+            "x86_64-unknown-linux-musl",
+            "armv7-unknown-linux-gnueabihf",
+            "riscv64gc-unknown-linux-gnu",
+            "x86_64-pc-windows-msvc",
+            "aarch64-apple-ios",
+        ] {
+            let target: Target = triple.parse().expect("Couldn't parse target");
+            assert!(!matches!(target, Target::Custom(_)), "{triple} is custom");
+            assert_eq!(target.to_string(), triple);
+        }
+
+        assert_eq!(
+            "x86_64-unknown-linux-musl".parse::<Target>().unwrap(),
+            Target::LinuxMusl
+        );
+    }
+
+    #[test]
+    fn custom_target_triples_round_trip_with_matching_library_names() {
+        let target: Target = "aarch64-pc-windows-gnullvm".parse().unwrap();
+        assert_eq!(
+            target,
+            Target::Custom("aarch64-pc-windows-gnullvm".to_string())
+        );
+        assert_eq!(target.as_str(), "aarch64-pc-windows-gnullvm");
+        assert_eq!(target.dynamic_lib_name("game"), "game.dll");
+
+        let target: Target = "aarch64-apple-tvos".parse().unwrap();
+        assert_eq!(target.dynamic_lib_name("game"), "libgame.dylib");
+
+        let target: Target = "powerpc64le-unknown-linux-gnu".parse().unwrap();
+        assert_eq!(target.dynamic_lib_name("game"), "libgame.so");
+
+        assert_eq!(Target::LinuxArmv7.dynamic_lib_name("game"), "libgame.so");
+    }
+
+    #[test]
+    fn legacy_target_names_map_to_known_targets() {
+        for (name, target) in [
+            ("linux", Target::Linux),
+            ("Linux-ARM", Target::LinuxArm),
+            ("linux-aarch64", Target::LinuxArm),
+            ("linux-musl", Target::LinuxMusl),
+            ("windows", Target::Windows),
+            ("mac", Target::Mac),
+            ("macos-arm", Target::MacArm),
+            ("darwin", Target::Mac),
+            ("android", Target::Android),
+            ("ios", Target::IOS),
+        ] {
+            assert_eq!(name.parse::<Target>().unwrap(), target, "{name}");
+        }
+    }
+
+    #[test]
+    fn the_current_target_matches_how_it_was_compiled() {
+        let Some(current) = Target::current() else {
+            return;
+        };
+        assert!(current.contains(std::env::consts::OS.replace("macos", "darwin").as_str()));
+        if cfg!(target_env = "musl") {
+            assert!(current.contains("-musl"), "{current}");
+        }
+        if cfg!(all(
+            target_os = "linux",
+            target_arch = "x86_64",
+            target_env = "gnu"
+        )) {
+            assert_eq!(current, Target::Linux);
+        }
+        if cfg!(all(target_arch = "arm", not(target_feature = "v7"))) {
+            assert!(!current.starts_with("armv7"), "{current}");
+        }
+    }
+
+    #[test]
+    fn invalid_target_triples_are_rejected() {
+        assert!("x86_64".parse::<Target>().is_err());
+        assert!("freebsd".parse::<Target>().is_err());
+        assert!("x86_64--linux".parse::<Target>().is_err());
+        assert!("x86_64-unknown-linux-gnu/../../etc"
+            .parse::<Target>()
+            .is_err());
+    }
 }